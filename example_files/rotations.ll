@@ -0,0 +1,24 @@
+; ModuleID = 'rotations'
+source_filename = "rotations"
+
+%Qubit = type opaque
+%Result = type opaque
+
+define void @rotations() #0 {
+entry:
+  call void @__quantum__qis__rx__body(double 0x3FF921FB54442D18, %Qubit* null)
+  call void @__quantum__qis__ry__body(double 0xBFE921FB54442D18, %Qubit* nonnull inttoptr (i64 1 to %Qubit*))
+  call void @__quantum__qis__rz__body(double 0x400921FB54442D18, %Qubit* null)
+  call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)
+  ret void
+}
+
+declare void @__quantum__qis__rx__body(double, %Qubit*)
+
+declare void @__quantum__qis__ry__body(double, %Qubit*)
+
+declare void @__quantum__qis__rz__body(double, %Qubit*)
+
+declare void @__quantum__qis__mz__body(%Qubit*, %Result*)
+
+attributes #0 = { "EntryPoint" "requiredQubits"="2" "requiredResults"="1" }
@@ -0,0 +1,717 @@
+//! Base Profile QIR, which hardware providers accept: qubits and results are
+//! static `inttoptr` addresses, the entry point is a single block of
+//! `__quantum__qis__*__body` calls in which no qubit is used after being
+//! measured, and results are reported with `__quantum__rt__result_record_output`.
+//!
+//! The Adaptive Profile keeps the static addresses but lets measured qubits
+//! be reused: each measurement result is read into an `i1` with
+//! `__quantum__qis__read_result__body`, conditionals branch on these bits,
+//! classical operations compute on them and they are reported with
+//! `__quantum__rt__bool_record_output`.
+
+use std::collections::HashSet;
+
+use inkwell::attributes::AttributeLoc;
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::module::Module;
+use inkwell::types::{BasicMetadataTypeEnum, PointerType};
+use inkwell::values::{BasicMetadataValueEnum, BasicValue, FunctionValue, IntValue, PointerValue};
+use inkwell::AddressSpace;
+
+use crate::circuit::{Circuit, Command, Conditional, OpBox, OpType, Operation, Register};
+use crate::classical::{emit_classical, is_classical};
+use crate::decompose::Gate;
+use crate::error::{CommandError, TketQirError};
+use crate::generate::{
+    check_arity, conditional_commands, custom_gate, eval, op_gates, permutation_swaps,
+    PermutationMode, Profile, QirOptions, GLOBAL_PHASE_ATTRIBUTE,
+};
+use crate::inline::{inline_circbox, inlined_phases};
+use crate::layout::RegisterLayout;
+use crate::params::{half_turns_to_radians, Bindings};
+
+/// Convert a pytket circuit to QIR of the Base or Adaptive Profile, as the
+/// options say. `targets` is the implicit permutation of the circuit and
+/// `phase` its own global phase.
+pub(crate) fn circuit_to_base_qir(
+    circ: &Circuit,
+    options: &QirOptions,
+    permutation: PermutationMode,
+    layout: &RegisterLayout,
+    targets: &[usize],
+    phase: f64,
+) -> Result<String, TketQirError> {
+    // The module and its entry point are named after the circuit, as in the
+    // full profile
+    let name = circ.name.as_ref().map_or("tket_circuit", String::as_str);
+    let context = Context::create();
+    let module = context.create_module(name);
+    let builder = context.create_builder();
+
+    let entry_point = module.add_function(name, context.void_type().fn_type(&[], false), None);
+    builder.position_at_end(context.append_basic_block(entry_point, "entry"));
+
+    // With `Relabel`, the static address of a qubit is that of the qubit its
+    // state ends up on
+    let (addresses, swaps) = match permutation {
+        PermutationMode::Relabel => (targets.to_vec(), vec![]),
+        PermutationMode::Swaps => ((0..targets.len()).collect(), permutation_swaps(targets)),
+    };
+    let mut emitter = BaseEmitter {
+        context: &context,
+        module: &module,
+        builder: &builder,
+        entry_point,
+        profile: options.profile,
+        layout,
+        addresses,
+        bindings: options.bindings.clone(),
+        measured: HashSet::new(),
+        bits: vec![None; layout.bits.len()],
+        phase,
+    };
+    for (index, com) in circ.commands.iter().enumerate() {
+        emitter.emit(com).map_err(|source| TketQirError::Command {
+            index,
+            opgroup: com.opgroup.as_ref().cloned(),
+            source,
+        })?;
+    }
+    let swap = Gate::new(OpType::SWAP, vec![], vec![0, 1]);
+    for (a, b) in swaps {
+        // The results of measured qubits are recorded already, so their
+        // states need not move, but they cannot be swapped with others
+        let measured = [a, b].map(|address| emitter.measured.contains(&address));
+        match measured {
+            [true, true] => {}
+            [false, false] => emitter.emit_gate(&swap, &[a, b]),
+            _ => {
+                let address = if measured[0] { a } else { b };
+                let qubit = layout.qubits.unit(address).expect("SWAPs are on qubits");
+                return Err(TketQirError::Command {
+                    index: circ.commands.len(),
+                    opgroup: None,
+                    source: CommandError::MeasuredQubit(qubit.clone()),
+                });
+            }
+        }
+    }
+
+    let adaptive = options.profile == Profile::Adaptive;
+    let label_type = context.i8_type().ptr_type(AddressSpace::Generic);
+    let (record_output, value_type): (_, BasicMetadataTypeEnum) = if adaptive {
+        (
+            "__quantum__rt__bool_record_output",
+            context.bool_type().into(),
+        )
+    } else {
+        (
+            "__quantum__rt__result_record_output",
+            emitter.pointer_type("Result").into(),
+        )
+    };
+    let record_output = emitter.function(record_output, &[value_type, label_type.into()]);
+    for index in 0..layout.bits.len() {
+        let value: BasicMetadataValueEnum = if adaptive {
+            emitter.bit(index).into()
+        } else {
+            emitter.address("Result", index).into()
+        };
+        emitter.call(record_output, &[value, label_type.const_null().into()]);
+    }
+    builder.build_return(None);
+
+    let attributes = [
+        ("EntryPoint", String::new()),
+        ("requiredQubits", layout.qubits.len().to_string()),
+        ("requiredResults", layout.bits.len().to_string()),
+    ];
+    for (key, value) in attributes {
+        let attribute = context.create_string_attribute(key, &value);
+        entry_point.add_attribute(AttributeLoc::Function, attribute);
+    }
+    let phase = emitter.phase.rem_euclid(2.0);
+    if phase != 0.0 {
+        let attribute = context.create_string_attribute(GLOBAL_PHASE_ATTRIBUTE, &phase.to_string());
+        entry_point.add_attribute(AttributeLoc::Function, attribute);
+    }
+
+    module
+        .verify()
+        .map_err(|err| TketQirError::Codegen(err.to_string()))?;
+    Ok(module.print_to_string().to_string())
+}
+
+/// State for emitting the commands of a circuit into a Base or Adaptive
+/// Profile entry point.
+struct BaseEmitter<'a, 'ctx> {
+    context: &'ctx Context,
+    module: &'a Module<'ctx>,
+    builder: &'a Builder<'ctx>,
+    entry_point: FunctionValue<'ctx>,
+    profile: Profile,
+    layout: &'a RegisterLayout,
+    // Static address of each qubit, by flat index
+    addresses: Vec<usize>,
+    bindings: Bindings,
+    // Addresses of the qubits measured so far, which the base profile
+    // cannot reuse
+    measured: HashSet<usize>,
+    // Value of each bit in the adaptive profile, by flat index, `None`
+    // while it is zero from the start
+    bits: Vec<Option<IntValue<'ctx>>>,
+    // Global phase, in half-turns, of the commands emitted so far
+    phase: f64,
+}
+
+impl<'a, 'ctx> BaseEmitter<'a, 'ctx> {
+    /// Static address of a qubit which is not yet measured.
+    fn qubit(&self, reg: &Register) -> Result<usize, CommandError> {
+        let address = self
+            .layout
+            .qubits
+            .index(reg)
+            .map(|index| self.addresses[index])
+            .ok_or_else(|| CommandError::UnknownRegister(reg.clone()))?;
+        if self.measured.contains(&address) {
+            return Err(CommandError::MeasuredQubit(reg.clone()));
+        }
+        Ok(address)
+    }
+
+    /// Static address of the result of a bit, its flat index.
+    fn result(&self, reg: &Register) -> Result<usize, CommandError> {
+        self.layout
+            .bits
+            .index(reg)
+            .ok_or_else(|| CommandError::UnknownRegister(reg.clone()))
+    }
+
+    /// Value of the bit at a flat index, in the adaptive profile.
+    fn bit(&self, index: usize) -> IntValue<'ctx> {
+        self.bits[index].unwrap_or_else(|| self.context.bool_type().const_zero())
+    }
+
+    fn emit(&mut self, com: &Command) -> Result<(), CommandError> {
+        let optype = &com.op.op_type;
+        match optype {
+            OpType::Measure => {
+                check_arity(com, 2)?;
+                let qubit = self.qubit(&com.args[0])?;
+                let result = self.result(&com.args[1])?;
+                let measure = self.function(
+                    "__quantum__qis__mz__body",
+                    &[
+                        self.pointer_type("Qubit").into(),
+                        self.pointer_type("Result").into(),
+                    ],
+                );
+                self.call(
+                    measure,
+                    &[
+                        self.address("Qubit", qubit).into(),
+                        self.address("Result", result).into(),
+                    ],
+                );
+                if self.profile == Profile::Adaptive {
+                    self.bits[result] = Some(self.read_result(result));
+                } else {
+                    self.measured.insert(qubit);
+                }
+            }
+            OpType::CircBox => {
+                let circuit = match com.op.op_box.as_ref() {
+                    Some(OpBox::CircBox { circuit, .. }) => circuit,
+                    _ => return Err(CommandError::MissingData(optype.clone(), "box")),
+                };
+                for inner in inline_circbox(circuit, &com.args)? {
+                    self.emit(&inner)?;
+                }
+                let phase = self.phases(&inlined_phases(circuit))?;
+                self.phase += phase;
+            }
+            OpType::CustomGate => {
+                let (bindings, commands, phases) = custom_gate(&com.op, &com.args, &self.bindings)?;
+                let bindings = std::mem::replace(&mut self.bindings, bindings);
+                let result = commands
+                    .iter()
+                    .try_for_each(|inner| self.emit(inner))
+                    .and_then(|()| self.phases(&phases));
+                self.bindings = bindings;
+                self.phase += result?;
+            }
+            // Barriers only constrain compilation
+            OpType::Barrier => {}
+            OpType::Conditional if self.profile == Profile::Adaptive => {
+                let (conditional, condition_bits, commands) = conditional_commands(com)?;
+                self.emit_if(conditional, condition_bits, &commands)?;
+            }
+            _ if is_classical(optype) && self.profile == Profile::Adaptive => {
+                self.emit_classical(&com.op, &com.args)?;
+            }
+            // The base profile has neither branches nor computations on
+            // results, and neither profile has arrays of controls
+            _ if is_classical(optype) => {
+                return Err(CommandError::NotInProfile(optype.clone(), self.profile))
+            }
+            OpType::Conditional | OpType::QControlBox => {
+                return Err(CommandError::NotInProfile(optype.clone(), self.profile))
+            }
+            _ => {
+                let (gates, phase) = op_gates(&com.op, com.args.len(), &self.bindings)?;
+                let qubits = com
+                    .args
+                    .iter()
+                    .map(|reg| self.qubit(reg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for gate in &gates {
+                    // Qubits cannot be reused after measurement in the base
+                    // profile, so neither reset
+                    if gate.op_type == OpType::Reset && self.profile == Profile::Base {
+                        return Err(CommandError::NotInProfile(OpType::Reset, self.profile));
+                    }
+                }
+                for gate in &gates {
+                    self.emit_gate(gate, &qubits);
+                }
+                self.phase += phase;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sum of phases given as Symengine Exprs.
+    fn phases(&self, phases: &[String]) -> Result<f64, CommandError> {
+        phases.iter().map(|phase| eval(phase, &self.bindings)).sum()
+    }
+
+    /// Read the result at a static address as an `i1`.
+    fn read_result(&self, result: usize) -> IntValue<'ctx> {
+        let name = "__quantum__qis__read_result__body";
+        let function = self.module.get_function(name).unwrap_or_else(|| {
+            let fn_type = self
+                .context
+                .bool_type()
+                .fn_type(&[self.pointer_type("Result").into()], false);
+            self.module.add_function(name, fn_type, None)
+        });
+        self.builder
+            .build_call(function, &[self.address("Result", result).into()], "result")
+            .try_as_basic_value()
+            .left()
+            .expect("read_result returns an i1")
+            .into_int_value()
+    }
+
+    /// Emit a classical operation on the bits `args`.
+    fn emit_classical(&mut self, op: &Operation, args: &[Register]) -> Result<(), CommandError> {
+        let indices = args
+            .iter()
+            .map(|bit| self.result(bit))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut read = |i: usize| self.bit(indices[i]);
+        let writes = emit_classical(self.context, self.builder, op, args, &mut read)?;
+        for (i, value) in writes {
+            self.bits[indices[i]] = Some(value);
+        }
+        Ok(())
+    }
+
+    /// Emit `commands` guarded by the values of `condition_bits`, where bit
+    /// `i` of the condition value is compared with `condition_bits[i]`. The
+    /// bits the commands write are merged with their previous value by phis.
+    fn emit_if(
+        &mut self,
+        conditional: &Conditional,
+        condition_bits: &[Register],
+        commands: &[Command],
+    ) -> Result<(), CommandError> {
+        let mut condition: Option<IntValue> = None;
+        for (i, bit) in condition_bits.iter().enumerate() {
+            let value = self.bit(self.result(bit)?);
+            let bit_condition = if (conditional.value.checked_shr(i as u32).unwrap_or(0) & 1) == 1 {
+                value
+            } else {
+                self.builder.build_not(value, "not")
+            };
+            condition = Some(match condition {
+                Some(acc) => self.builder.build_and(acc, bit_condition, "and"),
+                None => bit_condition,
+            });
+        }
+        let condition = condition.expect("conditions have a positive width");
+
+        let start = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned");
+        let then_block = self.context.append_basic_block(self.entry_point, "then");
+        let continue_block = self
+            .context
+            .append_basic_block(self.entry_point, "continue");
+        self.builder
+            .build_conditional_branch(condition, then_block, continue_block);
+
+        // The phase of the commands depends on the condition, so it is not
+        // global and is dropped
+        let (phase, before) = (self.phase, self.bits.clone());
+        self.builder.position_at_end(then_block);
+        for inst in commands {
+            self.emit(inst)?;
+        }
+        let then_end = self
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned");
+        self.builder.build_unconditional_branch(continue_block);
+        self.builder.position_at_end(continue_block);
+        self.phase = phase;
+
+        let after = std::mem::replace(&mut self.bits, before);
+        for (index, value) in after.into_iter().enumerate() {
+            if let Some(value) = value.filter(|&value| Some(value) != self.bits[index]) {
+                let phi = self.builder.build_phi(self.context.bool_type(), "bit");
+                phi.add_incoming(&[
+                    (&value as &dyn BasicValue, then_end),
+                    (&self.bit(index), start),
+                ]);
+                self.bits[index] = Some(phi.as_basic_value().into_int_value());
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit a native gate acting on positions into the static qubit
+    /// addresses `qubits`. Angles are given in half-turns and emitted in radians.
+    fn emit_gate(&self, gate: &Gate, qubits: &[usize]) {
+        let name = match gate.op_type {
+            OpType::H => "h__body",
+            OpType::X => "x__body",
+            OpType::Y => "y__body",
+            OpType::Z => "z__body",
+            OpType::S => "s__body",
+            OpType::Sdg => "s__adj",
+            OpType::T => "t__body",
+            OpType::Tdg => "t__adj",
+            OpType::Rx => "rx__body",
+            OpType::Ry => "ry__body",
+            OpType::Rz => "rz__body",
+            OpType::CX => "cnot__body",
+            OpType::CZ => "cz__body",
+            OpType::SWAP => "swap__body",
+            OpType::CCX => "ccx__body",
+            OpType::Reset => "reset__body",
+            _ => unreachable!("{:?} has no QIS function", gate.op_type),
+        };
+        let f64_type = self.context.f64_type();
+        let mut types: Vec<BasicMetadataTypeEnum> = vec![f64_type.into(); gate.params.len()];
+        let mut args: Vec<BasicMetadataValueEnum> = gate
+            .params
+            .iter()
+            .map(|&angle| f64_type.const_float(half_turns_to_radians(angle)).into())
+            .collect();
+        for &arg in &gate.args {
+            types.push(self.pointer_type("Qubit").into());
+            args.push(self.address("Qubit", qubits[arg]).into());
+        }
+        let function = self.function(&format!("__quantum__qis__{}", name), &types);
+        self.call(function, &args);
+    }
+
+    /// Get or declare the void function `name` taking `params`.
+    fn function(&self, name: &str, params: &[BasicMetadataTypeEnum<'ctx>]) -> FunctionValue<'ctx> {
+        self.module.get_function(name).unwrap_or_else(|| {
+            let fn_type = self.context.void_type().fn_type(params, false);
+            self.module.add_function(name, fn_type, None)
+        })
+    }
+
+    fn call(&self, function: FunctionValue<'ctx>, args: &[BasicMetadataValueEnum<'ctx>]) {
+        self.builder.build_call(function, args, "");
+    }
+
+    /// `%<name>*`, for the opaque `%Qubit` and `%Result` types.
+    fn pointer_type(&self, name: &str) -> PointerType<'ctx> {
+        self.module
+            .get_struct_type(name)
+            .unwrap_or_else(|| self.context.opaque_struct_type(name))
+            .ptr_type(AddressSpace::Generic)
+    }
+
+    /// The static address `inttoptr (i64 index to %<name>*)`, `null` for 0.
+    fn address(&self, name: &str, index: usize) -> PointerValue<'ctx> {
+        self.context
+            .i64_type()
+            .const_int(index as u64, false)
+            .const_to_pointer(self.pointer_type(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::json_to_qir;
+    use crate::parse::{function_to_circuit, ModuleExtension};
+
+    fn profile_qir(json: &str, profile: Profile) -> Result<String, TketQirError> {
+        let options = QirOptions {
+            profile,
+            ..QirOptions::default()
+        };
+        crate::generate::circuit_to_qir_with(&serde_json::from_str(json).unwrap(), &options)
+    }
+
+    fn base_qir(json: &str) -> Result<String, TketQirError> {
+        profile_qir(json, Profile::Base)
+    }
+
+    #[test]
+    fn test_base_profile() {
+        let ir = base_qir(
+            r#"{"bits": [["c", [0]], ["c", [1]]], "commands": [{"args": [["q", [0]]], "op": {"type": "H"}}, {"args": [["q", [0]], ["q", [1]]], "op": {"type": "CX"}}, {"args": [["q", [1]]], "op": {"params": ["0.5"], "type": "Rz"}}, {"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}, {"args": [["q", [1]], ["c", [1]]], "op": {"type": "Measure"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .expect("Conversion failed.");
+        assert!(ir.contains("call void @__quantum__qis__h__body(%Qubit* null)"));
+        assert!(ir.contains(
+            "call void @__quantum__qis__cnot__body(%Qubit* null, %Qubit* inttoptr (i64 1 to %Qubit*))"
+        ));
+        assert!(ir.contains("@__quantum__qis__rz__body(double"));
+        assert!(ir.contains(
+            "call void @__quantum__qis__mz__body(%Qubit* inttoptr (i64 1 to %Qubit*), %Result* inttoptr (i64 1 to %Result*))"
+        ));
+        assert_eq!(
+            ir.matches("call void @__quantum__rt__result_record_output(")
+                .count(),
+            2
+        );
+        assert!(ir.contains(r#""EntryPoint" "requiredQubits"="2" "requiredResults"="2""#));
+        assert!(!ir.contains("__quantum__rt__qubit_allocate"));
+        assert!(!ir.contains("%Array"));
+
+        let file = tempfile::Builder::new()
+            .suffix(".ll")
+            .tempfile()
+            .expect("Creating a file failed.");
+        std::fs::write(file.path(), ir).expect("Writing failed.");
+        let module = llvm_ir::Module::from_ir_path(file.path()).expect("Parsing failed.");
+        let func = module.get_entry_point().expect("No entry point found.");
+        let imported = function_to_circuit(func).expect("Import failed.");
+        assert_eq!(imported.qubits.len(), 2);
+        assert_eq!(imported.bits.len(), 2);
+        let op_types: Vec<_> = imported
+            .commands
+            .iter()
+            .map(|com| com.op.op_type.clone())
+            .collect();
+        assert_eq!(
+            op_types,
+            vec![
+                OpType::H,
+                OpType::CX,
+                OpType::Rz,
+                OpType::Measure,
+                OpType::Measure
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entry_point_name() {
+        // The entry point is named after the circuit, if it has a name
+        let circuit = |name: &str| {
+            format!(
+                r#"{{"bits": [], "commands": [{{"args": [["q", [0]]], "op": {{"type": "H"}}}}], "implicit_permutation": [], {}"phase": "0.0", "qubits": [["q", [0]]]}}"#,
+                name
+            )
+        };
+        for profile in [Profile::Base, Profile::Adaptive] {
+            let ir = profile_qir(&circuit(""), profile).expect("Conversion failed.");
+            assert!(ir.contains("define void @tket_circuit()"));
+            let ir = profile_qir(&circuit(r#""name": "prepare", "#), profile)
+                .expect("Conversion failed.");
+            assert!(ir.contains("define void @prepare()"));
+        }
+    }
+
+    #[test]
+    fn test_base_profile_errors() {
+        let err = base_qir(
+            r#"{"bits": [["c", [0]]], "commands": [{"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}, {"args": [["q", [0]]], "op": {"type": "H"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        );
+        assert!(matches!(
+            err,
+            Err(TketQirError::Command {
+                index: 1,
+                source: CommandError::MeasuredQubit(_),
+                ..
+            })
+        ));
+
+        let conditional = r#"{"bits": [["c", [0]]], "commands": [{"args": [["c", [0]], ["q", [0]]], "op": {"conditional": {"op": {"type": "Z"}, "value": 1, "width": 1}, "type": "Conditional"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#;
+        assert!(matches!(
+            base_qir(conditional),
+            Err(TketQirError::Command {
+                source: CommandError::NotInProfile(OpType::Conditional, Profile::Base),
+                ..
+            })
+        ));
+        // The full and adaptive profiles support it
+        json_to_qir(conditional).expect("Conversion failed.");
+        profile_qir(conditional, Profile::Adaptive).expect("Conversion failed.");
+    }
+
+    #[test]
+    fn test_permutation() {
+        // The state of q[0] ends up on q[1] and that of q[1] on q[0]
+        let circ = r#"{"bits": [["c", [0]]], "commands": [{"args": [["q", [0]]], "op": {"type": "X"}}, {"args": [["q", [1]], ["c", [0]]], "op": {"type": "Measure"}}], "implicit_permutation": [[["q", [0]], ["q", [1]]], [["q", [1]], ["q", [0]]]], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#;
+        let relabelled = base_qir(circ).expect("Conversion failed.");
+        assert!(relabelled
+            .contains("call void @__quantum__qis__x__body(%Qubit* inttoptr (i64 1 to %Qubit*))"));
+        assert!(
+            relabelled.contains("call void @__quantum__qis__mz__body(%Qubit* null, %Result* null)")
+        );
+        assert!(!relabelled.contains("swap"));
+
+        let options = QirOptions {
+            profile: Profile::Base,
+            permutation: Some(PermutationMode::Swaps),
+            ..QirOptions::default()
+        };
+        let swapped = |circ: &str| {
+            crate::generate::circuit_to_qir_with(&serde_json::from_str(circ).unwrap(), &options)
+        };
+        // The SWAP would use q[1] after it is measured
+        assert!(matches!(
+            swapped(circ),
+            Err(TketQirError::Command {
+                index: 2,
+                source: CommandError::MeasuredQubit(Register(_, ref index)),
+                ..
+            }) if index == &[1]
+        ));
+
+        // Once both are measured, nothing is left to move
+        let measured = circ.replace(
+            r#"{"args": [["q", [0]]], "op": {"type": "X"}}"#,
+            r#"{"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}"#,
+        );
+        let ir = swapped(&measured).expect("Conversion failed.");
+        assert_eq!(
+            ir.matches("call void @__quantum__qis__mz__body(").count(),
+            2
+        );
+        assert!(!ir.contains("swap"));
+
+        let unmeasured = circ.replace(
+            r#"{"args": [["q", [1]], ["c", [0]]], "op": {"type": "Measure"}}"#,
+            r#"{"args": [["q", [1]]], "op": {"type": "H"}}"#,
+        );
+        let ir = swapped(&unmeasured).expect("Conversion failed.");
+        assert!(ir.contains("call void @__quantum__qis__x__body(%Qubit* null)"));
+        assert!(ir.contains(
+            "call void @__quantum__qis__swap__body(%Qubit* inttoptr (i64 1 to %Qubit*), %Qubit* null)"
+        ));
+    }
+
+    #[test]
+    fn test_adaptive_profile() {
+        let ir = profile_qir(
+            r#"{"bits": [["c", [0]], ["c", [1]], ["c", [2]]], "commands": [{"args": [["q", [0]]], "op": {"type": "H"}}, {"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}, {"args": [["c", [0]], ["q", [0]]], "op": {"conditional": {"op": {"type": "X"}, "value": 1, "width": 1}, "type": "Conditional"}}, {"args": [["c", [0]], ["c", [1]]], "op": {"conditional": {"op": {"classical": {"n_i": 0, "n_io": 0, "n_o": 1, "name": "SetBits", "values": [true]}, "type": "SetBits"}, "value": 1, "width": 1}, "type": "Conditional"}}, {"args": [["c", [0]], ["c", [1]], ["c", [2]]], "op": {"classical": {"lower": 1, "n_i": 2, "n_io": 0, "n_o": 1, "name": "RangePredicate", "upper": 2}, "type": "RangePredicate"}}, {"args": [["q", [0]]], "op": {"type": "Reset"}}, {"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+            Profile::Adaptive,
+        )
+        .expect("Conversion failed.");
+        // The qubit is reset and reused after being measured
+        assert!(ir.contains("call void @__quantum__qis__reset__body(%Qubit* null)"));
+        assert_eq!(
+            ir.matches("call void @__quantum__qis__mz__body(").count(),
+            2
+        );
+        assert_eq!(
+            ir.matches("call i1 @__quantum__qis__read_result__body(")
+                .count(),
+            2
+        );
+        assert!(ir.contains("br i1"));
+        assert!(ir.contains("phi i1"));
+        assert!(ir.contains("icmp uge i64"));
+        assert_eq!(
+            ir.matches("call void @__quantum__rt__bool_record_output(i1")
+                .count(),
+            3
+        );
+        assert!(!ir.contains("result_record_output"));
+
+        // Groups of bits are only computed on in the adaptive profile
+        let multi_bit = r#"{"bits": [["c", [0]], ["c", [1]], ["c", [2]], ["c", [3]]], "commands": [{"args": [["c", [0]], ["c", [1]], ["c", [2]], ["c", [3]]], "op": {"classical": {"n": 2, "op": {"classical": {"n_i": 0, "n_io": 0, "n_o": 2, "name": "SetBits", "values": [true, false]}, "type": "SetBits"}}, "type": "MultiBit"}}], "implicit_permutation": [], "phase": "0.0", "qubits": []}"#;
+        profile_qir(multi_bit, Profile::Adaptive).expect("Conversion failed.");
+        assert!(matches!(
+            base_qir(multi_bit),
+            Err(TketQirError::Command {
+                source: CommandError::NotInProfile(OpType::MultiBit, Profile::Base),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_classical_exp_box() {
+        let circ = std::fs::read_to_string("example_files/pytket/classical_exp.json")
+            .expect("Reading failed.");
+        let ir = profile_qir(&circ, Profile::Adaptive).expect("Conversion failed.");
+        assert!(ir.contains(" = xor i1 "));
+        assert!(ir.contains(" = add i64 "));
+        assert!(ir.contains(" = xor i64 "));
+        assert!(ir.contains(" = lshr i64 "));
+        assert_eq!(
+            ir.matches("call void @__quantum__rt__bool_record_output(i1")
+                .count(),
+            5
+        );
+        assert!(matches!(
+            base_qir(&circ),
+            Err(TketQirError::Command {
+                index: 2,
+                source: CommandError::NotInProfile(OpType::ClassicalExpBox, Profile::Base),
+                ..
+            })
+        ));
+
+        let exp_box = |exp: &str| {
+            format!(
+                r#"{{"bits": [["a", [0]], ["a", [1]], ["b", [0]]], "commands": [{{"args": [["a", [0]], ["a", [1]], ["b", [0]]], "op": {{"box": {{"exp": {}, "id": "4b5cc2b0-3f5c-4a56-9d1c-5b0cf5a1c3a4", "n_i": 2, "n_io": 0, "n_o": 1, "type": "ClassicalExpBox"}}, "type": "ClassicalExpBox"}}}}], "implicit_permutation": [], "phase": "0.0", "qubits": []}}"#,
+                exp
+            )
+        };
+        let ir = profile_qir(
+            &exp_box(r#"{"args": [{"args": [{"name": "a", "size": 2}, 3], "op": "RegWiseOp.POW"}, 4], "op": "RegWiseOp.GEQ"}"#),
+            Profile::Adaptive,
+        )
+        .expect("Conversion failed.");
+        assert!(ir.contains(" = mul i64 "));
+        assert!(ir.contains(" = icmp uge i64 "));
+
+        let err = profile_qir(
+            &exp_box(r#"{"args": [["a", [0]], ["c", [0]]], "op": "BitWiseOp.AND"}"#),
+            Profile::Adaptive,
+        );
+        assert!(matches!(
+            err,
+            Err(TketQirError::Command {
+                source: CommandError::UnknownRegister(_),
+                ..
+            })
+        ));
+        let err = profile_qir(
+            &exp_box(r#"{"args": [{"name": "a", "size": 2}, 2], "op": "RegWiseOp.MOD"}"#),
+            Profile::Adaptive,
+        );
+        assert!(matches!(
+            err,
+            Err(TketQirError::Command {
+                source: CommandError::UnsupportedExpression(ref op),
+                ..
+            }) if op == "RegWiseOp.MOD"
+        ));
+    }
+}
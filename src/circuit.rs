@@ -1,21 +1,18 @@
+use num_complex::Complex64;
 use serde::ser::Error as SerdeError;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
 
 // Use a Patch enum to distinguish between a missing value and a null value.
 // https://stackoverflow.com/questions/44331037/how-can-i-distinguish-between-a-deserialized-field-that-is-missing-and-one-that/44332837#44332837
-#[derive(Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub enum Patch<T> {
+    #[default]
     Missing,
     Null,
     Value(T),
 }
 
-impl<T> Default for Patch<T> {
-    fn default() -> Self {
-        Patch::Missing
-    }
-}
-
 impl<T> From<Option<T>> for Patch<T> {
     fn from(opt: Option<T>) -> Patch<T> {
         match opt {
@@ -53,6 +50,14 @@ impl<T> Patch<T> {
     pub fn is_missing(&self) -> bool {
         matches!(self, Patch::Missing)
     }
+
+    /// The value, if the field is neither missing nor null.
+    pub fn as_ref(&self) -> Option<&T> {
+        match self {
+            Patch::Value(v) => Some(v),
+            Patch::Null | Patch::Missing => None,
+        }
+    }
 }
 
 impl<T: Serialize> Serialize for Patch<T> {
@@ -73,85 +78,455 @@ impl<T: Serialize> Serialize for Patch<T> {
     }
 }
 
+/// Fields of an object that the models below do not know, kept so that
+/// serialising it again gives back what pytket wrote.
+pub type Fields = Map<String, Value>;
+
 /// Pytket specific models
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Register(pub String, pub Vec<u64>);
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct CompositeGate {
     // List of Symbols
-    args: Vec<String>,
-    definition: Box<Circuit>,
-    name: String,
+    pub args: Vec<String>,
+    pub definition: Box<Circuit>,
+    pub name: String,
+    #[serde(flatten)]
+    pub extra: Fields,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct BoxID(pub uuid::Uuid);
 
+/// Square complex matrix, by rows, serialised as pytket does with each
+/// entry a `[re, im]` pair. Entries are written back from their values, so
+/// their text may differ from pytket's, e.g. `1e-05` becomes `0.00001`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComplexMatrix(pub Vec<Vec<Complex64>>);
+
+impl Serialize for ComplexMatrix {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(
+            self.0
+                .iter()
+                .map(|row| row.iter().map(|x| (x.re, x.im)).collect::<Vec<_>>()),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for ComplexMatrix {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let rows = Vec::<Vec<(f64, f64)>>::deserialize(deserializer)?;
+        Ok(ComplexMatrix(
+            rows.into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|(re, im)| Complex64::new(re, im))
+                        .collect()
+                })
+                .collect(),
+        ))
+    }
+}
+
 /// Box for an operation, the enum variant names come from the names
 /// of the C++ operations and are renamed if the string corresponding
-/// to the operation is differently named when serializing.
+/// to the operation is differently named when serializing. Boxes of
+/// other types are kept whole as `Unknown`.
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
-#[serde(tag = "type")]
+#[serde(remote = "Self", tag = "type")]
 pub enum OpBox {
     CircBox {
         id: BoxID,
         circuit: Circuit,
+        #[serde(flatten)]
+        extra: Fields,
     },
     Unitary1qBox {
         id: BoxID,
-        // 2x2 matrix of complex numbers
-        matrix: [[(f32, f32); 2]; 2],
+        // 2x2 unitary matrix
+        matrix: ComplexMatrix,
+        #[serde(flatten)]
+        extra: Fields,
     },
     Unitary2qBox {
         id: BoxID,
-        // 4x4 matrix of complex numbers
-        matrix: [[(f32, f32); 4]; 4],
+        // 4x4 unitary matrix
+        matrix: ComplexMatrix,
+        #[serde(flatten)]
+        extra: Fields,
     },
     ExpBox {
         id: BoxID,
-        // 4x4 matrix of complex numbers
-        matrix: [[(f32, f32); 4]; 4],
-        phase: f64,
+        // Hermitian matrix A of the box's unitary e^{itA}
+        matrix: ComplexMatrix,
+        // The coefficient t, which pytket calls the phase
+        #[serde(rename = "phase")]
+        t: f64,
+        #[serde(flatten)]
+        extra: Fields,
     },
     PauliExpBox {
         id: BoxID,
-        paulis: Vec<String>,
+        paulis: Vec<Pauli>,
         // Symengine Expr
         phase: String,
+        #[serde(flatten)]
+        extra: Fields,
     },
     PhasePolyBox {
         id: BoxID,
         n_qubits: u32,
-        qubit_indices: Vec<(u32, u32)>,
+        // Index of each qubit of the box in the parities below
+        qubit_indices: Vec<(Register, u32)>,
+        // Parities of the qubits with the Rz angle applied to them,
+        // as Symengine Expr
+        phase_polynomial: Vec<(Vec<bool>, String)>,
+        // Rows of the boolean matrix mapping input to output parities
+        linear_transformation: Vec<Vec<bool>>,
+        #[serde(flatten)]
+        extra: Fields,
     },
+    #[serde(rename = "CustomGate")]
     Composite {
         id: BoxID,
         gate: CompositeGate,
         // Vec of Symengine Expr
         params: Vec<String>,
+        #[serde(flatten)]
+        extra: Fields,
     },
     QControlBox {
         id: BoxID,
         n_controls: u32,
         op: Box<Operation>,
+        // Values of the controls on which the op applies, big-endian, all
+        // ones when missing
+        #[serde(default, skip_serializing_if = "Patch::is_missing")]
+        control_state: Patch<u64>,
+        #[serde(flatten)]
+        extra: Fields,
+    },
+    ClassicalExpBox {
+        id: BoxID,
+        // Numbers of input, input and output, and output bits, in the
+        // order of the command's arguments
+        n_i: u32,
+        n_io: u32,
+        n_o: u32,
+        exp: ClassicalExp,
+        #[serde(flatten)]
+        extra: Fields,
     },
+    #[serde(skip)]
+    Unknown(Fields),
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
-pub enum OpType {
-    H,
-    CX,
-    Measure,
+impl OpBox {
+    // Serialised types of the variants above
+    const TYPES: &'static [&'static str] = &[
+        "CircBox",
+        "Unitary1qBox",
+        "Unitary2qBox",
+        "ExpBox",
+        "PauliExpBox",
+        "PhasePolyBox",
+        "CustomGate",
+        "QControlBox",
+        "ClassicalExpBox",
+    ];
+}
+
+impl Serialize for OpBox {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            OpBox::Unknown(fields) => fields.serialize(serializer),
+            _ => OpBox::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OpBox {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let fields = Fields::deserialize(deserializer)?;
+        match fields.get("type").and_then(Value::as_str) {
+            Some(op_type) if OpBox::TYPES.contains(&op_type) => {
+                OpBox::deserialize(Value::Object(fields)).map_err(serde::de::Error::custom)
+            }
+            _ => Ok(OpBox::Unknown(fields)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pauli {
+    I,
     X,
     Y,
     Z,
+}
+
+/// Define `OpType` from its variants, each serialised as its name unless
+/// another is given.
+macro_rules! op_types {
+    ($($variant:ident $(= $name:literal)?,)*) => {
+        /// Type of an operation, covering pytket's catalogue. Other names
+        /// deserialise to `Unknown`, so that every circuit pytket produces
+        /// round-trips even when its operations cannot be converted.
+        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum OpType {
+            $($variant,)*
+            Unknown(String),
+        }
+
+        impl OpType {
+            const KNOWN: &'static [OpType] = &[$(OpType::$variant,)*];
+
+            /// Name of the operation in pytket's serialisation.
+            pub fn name(&self) -> &str {
+                match self {
+                    $(OpType::$variant => op_types!(@name $variant $($name)?),)*
+                    OpType::Unknown(name) => name,
+                }
+            }
+        }
+    };
+    (@name $variant:ident) => { stringify!($variant) };
+    (@name $variant:ident $name:literal) => { $name };
+}
+
+op_types! {
+    // Boundaries and control flow
+    Input,
+    Output,
+    Create,
+    Discard,
+    ClInput,
+    ClOutput,
+    Barrier,
+    Label,
+    Branch,
+    Goto,
+    Stop,
+    // Classical operations
+    ClassicalTransform,
+    SetBits,
+    CopyBits,
+    RangePredicate,
+    ExplicitPredicate,
+    ExplicitModifier,
+    MultiBit,
+    WASM,
+    ClExpr,
+    // Single-qubit gates
+    Z,
+    X,
+    Y,
+    S,
+    Sdg,
+    T,
+    Tdg,
+    V,
+    Vdg,
+    SX,
+    SXdg,
+    H,
     Rx,
     Ry,
     Rz,
-    Conditional,
+    U3,
+    U2,
+    U1,
+    TK1,
+    PhasedX,
+    GPI,
+    GPI2,
+    Phase,
+    // Multi-qubit gates
+    CX,
+    CY,
+    CZ,
+    CH,
+    CV,
+    CVdg,
+    CSX,
+    CSXdg,
+    CRz,
+    CRx,
+    CRy,
+    CU1,
+    CU3,
+    PhaseGadget,
+    CS,
+    CSdg,
+    CCX,
+    SWAP,
+    CSWAP,
+    BRIDGE,
+    Noop = "noop",
+    ECR,
+    ISWAP,
+    NPhasedX,
+    ZZMax,
+    XXPhase,
+    YYPhase,
+    ZZPhase,
+    XXPhase3,
+    ESWAP,
+    FSim,
+    Sycamore,
+    ISWAPMax,
+    PhasedISWAP,
+    TK2,
+    AAMS,
+    CnRx,
+    CnRy,
+    CnRz,
+    CnX,
+    CnY,
+    CnZ,
+    // Measurement and reset
+    Measure,
+    Collapse,
+    Reset,
+    // Boxes
     CircBox,
+    Unitary1qBox,
+    Unitary2qBox,
+    Unitary3qBox,
+    ExpBox,
+    PauliExpBox,
+    PhasePolyBox,
+    QControlBox,
+    CustomGate,
+    ClassicalExpBox,
+    ProjectorAssertionBox,
+    StabiliserAssertionBox,
+    UnitaryTableauBox,
+    ToffoliBox,
+    MultiplexorBox,
+    MultiplexedRotationBox,
+    MultiplexedU2Box,
+    MultiplexedTensoredU1Box,
+    StatePreparationBox,
+    DiagonalBox,
+    ConjugationBox,
+    PauliExpPairBox,
+    PauliExpCommutingSetBox,
+    TermSequenceBox,
+    DummyBox,
+    Conditional,
+}
+
+impl OpType {
+    /// Operation with the given name in pytket's serialisation.
+    pub fn from_name(name: &str) -> Self {
+        OpType::KNOWN
+            .iter()
+            .find(|op_type| op_type.name() == name)
+            .cloned()
+            .unwrap_or_else(|| OpType::Unknown(name.to_string()))
+    }
+}
+
+impl Serialize for OpType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for OpType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(|name| OpType::from_name(&name))
+    }
+}
+
+/// Data of a classical operation. Its bits are its `n_i` inputs, then the
+/// `n_io` bits it both reads and writes, then its `n_o` outputs.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Classical {
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub n_i: Patch<u32>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub n_io: Patch<u32>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub n_o: Patch<u32>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub name: Patch<String>,
+    // Bits set by SetBits, truth table of an ExplicitPredicate or
+    // ExplicitModifier, or output of a ClassicalTransform for each input
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub values: Patch<ClassicalValues>,
+    // Bounds of a RangePredicate
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub lower: Patch<u64>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub upper: Patch<u64>,
+    // Operation a MultiBit applies `n` times
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub op: Patch<Box<Operation>>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub n: Patch<u32>,
+    #[serde(flatten)]
+    pub extra: Fields,
+}
+
+/// Values of a classical operation, bits or words whose bit `i` is that of
+/// the `i`-th bit of the operation.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ClassicalValues {
+    Bits(Vec<bool>),
+    Words(Vec<u64>),
+}
+
+/// Expression of a ClassicalExpBox, pytket's `LogicExp`: an operation such
+/// as `BitWiseOp.XOR` or `RegWiseOp.ADD` applied to its arguments.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ClassicalExp {
+    pub op: String,
+    pub args: Vec<ClassicalExpArg>,
+    #[serde(flatten)]
+    pub extra: Fields,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ClassicalExpArg {
+    Constant(u64),
+    Bit(Register),
+    Register(BitRegister),
+    Exp(Box<ClassicalExp>),
+}
+
+/// Register of the bits `name[0]` to `name[size - 1]`, read as the number
+/// whose bit `i` is `name[i]`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct BitRegister {
+    pub name: String,
+    pub size: u32,
+    #[serde(flatten)]
+    pub extra: Fields,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -159,45 +534,235 @@ pub struct Conditional {
     pub op: Box<Operation>,
     pub width: u32,
     pub value: u32,
+    #[serde(flatten)]
+    pub extra: Fields,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Operation {
     #[serde(rename = "type")]
     pub op_type: OpType,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub n_qb: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub params: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub n_qb: Patch<u32>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub params: Patch<Vec<String>>,
     #[serde(rename = "box")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub op_box: Option<OpBox>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub signature: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub conditional: Option<Conditional>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub op_box: Patch<OpBox>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub signature: Patch<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub conditional: Patch<Conditional>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub classical: Patch<Classical>,
+    #[serde(flatten)]
+    pub extra: Fields,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Command {
     pub op: Operation,
     pub args: Vec<Register>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub opgroup: Option<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub opgroup: Patch<String>,
+    #[serde(flatten)]
+    pub extra: Fields,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Permutation(pub Register, pub Register);
 
 /// Pytket canonical circuit
+///
+/// Serialising it sorts the keys of every object, as pytket does, so that
+/// a circuit pytket wrote serialises back to the same JSON.
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(remote = "Self")]
 pub struct Circuit {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Patch::is_missing")]
+    pub name: Patch<String>,
     // Symengine Expr
     pub phase: String,
     pub commands: Vec<Command>,
     pub qubits: Vec<Register>,
     pub bits: Vec<Register>,
     pub implicit_permutation: Vec<Permutation>,
+    // Such as `created_qubits` and `discarded_qubits`
+    #[serde(flatten)]
+    pub extra: Fields,
+}
+
+impl Serialize for Circuit {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let value =
+            Circuit::serialize(self, serde_json::value::Serializer).map_err(SerdeError::custom)?;
+        SortedKeys(&value).serialize(serializer)
+    }
+}
+
+/// A JSON value serialised with the keys of each object in order, whatever
+/// order the maps of `serde_json` keep.
+struct SortedKeys<'a>(&'a Value);
+
+impl Serialize for SortedKeys<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            Value::Object(fields) => {
+                let mut fields: Vec<_> = fields.iter().collect();
+                fields.sort_by_key(|(key, _)| *key);
+                serializer.collect_map(
+                    fields
+                        .into_iter()
+                        .map(|(key, value)| (key, SortedKeys(value))),
+                )
+            }
+            Value::Array(values) => serializer.collect_seq(values.iter().map(SortedKeys)),
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Circuit {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Circuit::deserialize(deserializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Remove the whitespace outside strings
+    fn minify(json: &str) -> String {
+        let mut minified = String::new();
+        let (mut in_string, mut escaped) = (false, false);
+        for c in json.chars() {
+            if in_string {
+                in_string = escaped || c != '"';
+                escaped = !escaped && c == '\\';
+            } else if c.is_whitespace() {
+                continue;
+            } else {
+                in_string = c == '"';
+            }
+            minified.push(c);
+        }
+        minified
+    }
+
+    #[test]
+    fn test_op_type_names() {
+        for op_type in OpType::KNOWN {
+            assert_eq!(&OpType::from_name(op_type.name()), op_type);
+        }
+        assert_eq!(OpType::Noop.name(), "noop");
+        for name in [
+            "ToffoliBox",
+            "MultiplexorBox",
+            "MultiplexedRotationBox",
+            "MultiplexedU2Box",
+            "MultiplexedTensoredU1Box",
+            "StatePreparationBox",
+            "DiagonalBox",
+            "ConjugationBox",
+            "PauliExpPairBox",
+            "PauliExpCommutingSetBox",
+            "TermSequenceBox",
+            "DummyBox",
+            "CnRx",
+            "CnRz",
+        ] {
+            assert!(
+                !matches!(OpType::from_name(name), OpType::Unknown(_)),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&OpType::ISWAPMax).unwrap(),
+            r#""ISWAPMax""#
+        );
+    }
+
+    #[test]
+    fn test_unknown_ops_round_trip() {
+        let json = r#"{"bits":[],"commands":[{"op":{"type":"Barrier","signature":["Q","Q"]},"args":[["q",[0]],["q",[1]]]},{"op":{"type":"SomeFutureGate","params":["0.5"]},"args":[["q",[0]]]}],"implicit_permutation":[],"phase":"0.0","qubits":[["q",[0]],["q",[1]]]}"#;
+        let circ: Circuit = serde_json::from_str(json).unwrap();
+        assert_eq!(circ.commands[0].op.op_type, OpType::Barrier);
+        assert_eq!(
+            circ.commands[1].op.op_type,
+            OpType::Unknown("SomeFutureGate".to_string())
+        );
+        let reserialised: Circuit =
+            serde_json::from_str(&serde_json::to_string(&circ).unwrap()).unwrap();
+        assert_eq!(reserialised, circ);
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let corpus = std::fs::read_dir("example_files/pytket")
+            .expect("Directory not found.")
+            .map(|entry| entry.unwrap().path());
+        let pytket_output = std::path::PathBuf::from("example_files/simple_H_pytket_circuit.json");
+        for path in corpus.chain([pytket_output]) {
+            let json = std::fs::read_to_string(&path).expect("Reading failed.");
+            let circ: Circuit = serde_json::from_str(&json).expect("Error while reading.");
+            let reserialised = serde_json::to_string(&circ).unwrap();
+            assert_eq!(reserialised, minify(&json), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_matrix_number_text() {
+        let json = "[[[1e-05,0.0],[0.5,-0.0]],[[0.0,0.0],[1.0,0.0]]]";
+        let matrix: ComplexMatrix = serde_json::from_str(json).unwrap();
+        let reserialised = serde_json::to_string(&matrix).unwrap();
+        assert_eq!(
+            reserialised,
+            "[[[0.00001,0.0],[0.5,-0.0]],[[0.0,0.0],[1.0,0.0]]]"
+        );
+        assert_eq!(
+            serde_json::from_str::<ComplexMatrix>(&reserialised).unwrap(),
+            matrix
+        );
+    }
+
+    #[test]
+    fn test_missing_and_null_fields() {
+        let json = r#"{"bits":[],"commands":[{"args":[["q",[0]]],"op":{"box":{"id":"2b3c4d5e-6f7a-4b8c-9d0e-1f2a3b4c5d6e","type":"FutureBox"},"params":null,"type":"FutureBox"},"opgroup":null}],"implicit_permutation":[],"name":null,"phase":"0.0","qubits":[["q",[0]]]}"#;
+        let circ: Circuit = serde_json::from_str(json).unwrap();
+        assert_eq!(circ.name, Patch::Null);
+        let op = &circ.commands[0].op;
+        assert_eq!(op.params, Patch::Null);
+        assert_eq!(op.signature, Patch::Missing);
+        assert!(matches!(op.op_box, Patch::Value(OpBox::Unknown(_))));
+        assert_eq!(serde_json::to_string(&circ).unwrap(), json);
+    }
+
+    #[test]
+    fn test_sorted_keys() {
+        let mut circ: Circuit = serde_json::from_str(
+            r#"{"bits":[],"commands":[],"implicit_permutation":[],"phase":"0.0","qubits":[]}"#,
+        )
+        .unwrap();
+        // Inserted out of order, which maps keeping the insertion order would
+        // serialise as is
+        circ.extra
+            .insert("zeta".to_string(), serde_json::json!({"b": 1, "a": 2}));
+        circ.extra
+            .insert("alpha".to_string(), serde_json::json!([{"y": 0, "x": 0}]));
+        assert_eq!(
+            serde_json::to_string(&circ).unwrap(),
+            r#"{"alpha":[{"x":0,"y":0}],"bits":[],"commands":[],"implicit_permutation":[],"phase":"0.0","qubits":[],"zeta":{"a":2,"b":1}}"#
+        );
+    }
 }
@@ -0,0 +1,450 @@
+//! Classical operations on bits, emitted as integer arithmetic on `i1`
+//! values. Each profile reads the bits an operation uses into such values
+//! and stores back those it writes.
+//!
+//! The expressions of ClassicalExpBoxes compute on bits as `i1` and on
+//! registers as `i64`, so registers have at most 64 bits and register
+//! arithmetic wraps around at 2^64.
+
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::values::IntValue;
+use inkwell::IntPredicate;
+
+use crate::circuit::{
+    Classical, ClassicalExp, ClassicalExpArg, ClassicalValues, OpBox, OpType, Operation, Patch,
+    Register,
+};
+use crate::error::CommandError;
+use crate::generate::check_op_arity;
+
+/// Whether `op_type` is a classical operation, see [`emit_classical`].
+pub(crate) fn is_classical(op_type: &OpType) -> bool {
+    matches!(
+        op_type,
+        OpType::SetBits
+            | OpType::CopyBits
+            | OpType::RangePredicate
+            | OpType::ExplicitPredicate
+            | OpType::ExplicitModifier
+            | OpType::ClassicalTransform
+            | OpType::MultiBit
+            | OpType::ClassicalExpBox
+    )
+}
+
+/// Emit the classical operation `op` on the bits `args`, `read` giving the
+/// value of the bit at a position. Returns the new value of each bit the
+/// operation writes, by position.
+pub(crate) fn emit_classical<'ctx>(
+    context: &'ctx Context,
+    builder: &Builder<'ctx>,
+    op: &Operation,
+    args: &[Register],
+    read: &mut dyn FnMut(usize) -> IntValue<'ctx>,
+) -> Result<Vec<(usize, IntValue<'ctx>)>, CommandError> {
+    let optype = &op.op_type;
+    let missing = |data| CommandError::MissingData(optype.clone(), data);
+    let n_args = args.len();
+    if *optype == OpType::ClassicalExpBox {
+        return match op.op_box.as_ref() {
+            Some(OpBox::ClassicalExpBox {
+                n_i,
+                n_io,
+                n_o,
+                exp,
+                ..
+            }) => {
+                let n_read = (n_i + n_io) as usize;
+                check_op_arity(optype, n_args, n_read + *n_o as usize)?;
+                let mut emitter = ExpEmitter {
+                    context,
+                    builder,
+                    args: &args[..n_read],
+                    read,
+                };
+                let value = emitter.eval(exp)?;
+                emitter.write(exp, value, *n_i as usize, &args[*n_i as usize..])
+            }
+            _ => Err(missing("box")),
+        };
+    }
+    let classical = op.classical.as_ref().ok_or_else(|| missing("classical"))?;
+    let count = |field: &Patch<u32>, data| {
+        field
+            .as_ref()
+            .map(|&n| n as usize)
+            .ok_or_else(|| missing(data))
+    };
+
+    match optype {
+        OpType::SetBits => {
+            let values = bits(classical, optype, None)?;
+            check_op_arity(optype, n_args, values.len())?;
+            let bool_type = context.bool_type();
+            Ok(values
+                .iter()
+                .enumerate()
+                .map(|(i, &value)| (i, bool_type.const_int(value as u64, false)))
+                .collect())
+        }
+        OpType::CopyBits => {
+            let n = count(&classical.n_i, "n_i")?;
+            check_op_arity(optype, n_args, 2 * n)?;
+            Ok((0..n).map(|i| (n + i, read(i))).collect())
+        }
+        OpType::RangePredicate => {
+            let n = count(&classical.n_i, "n_i")?;
+            check_op_arity(optype, n_args, n + 1)?;
+            if n > 64 {
+                return Err(CommandError::UnsupportedOp(optype.clone()));
+            }
+            let lower = *classical.lower.as_ref().ok_or_else(|| missing("lower"))?;
+            let upper = *classical.upper.as_ref().ok_or_else(|| missing("upper"))?;
+            let inputs: Vec<_> = (0..n).map(&mut *read).collect();
+            let value = word(context, builder, &inputs);
+            let i64_type = context.i64_type();
+            let above = builder.build_int_compare(
+                IntPredicate::UGE,
+                value,
+                i64_type.const_int(lower, false),
+                "above",
+            );
+            let below = builder.build_int_compare(
+                IntPredicate::ULE,
+                value,
+                i64_type.const_int(upper, false),
+                "below",
+            );
+            Ok(vec![(n, builder.build_and(above, below, "in_range"))])
+        }
+        // The output, or the bit read and written, is the table entry of the
+        // bits read
+        OpType::ExplicitPredicate | OpType::ExplicitModifier => {
+            let n = count(&classical.n_i, "n_i")?;
+            check_op_arity(optype, n_args, n + 1)?;
+            let n_read = if *optype == OpType::ExplicitModifier {
+                n + 1
+            } else {
+                n
+            };
+            let table = bits(classical, optype, Some(n_read))?;
+            let inputs: Vec<_> = (0..n_read).map(&mut *read).collect();
+            Ok(vec![(
+                n,
+                lookup(context, builder, &inputs, |index| table[index]),
+            )])
+        }
+        OpType::ClassicalTransform => {
+            let n = count(&classical.n_io, "n_io")?;
+            check_op_arity(optype, n_args, n)?;
+            let words = match classical.values.as_ref() {
+                Some(ClassicalValues::Words(words)) if Some(words.len()) == table_len(n) => words,
+                // An empty table deserialises as bits
+                _ => return Err(missing("values")),
+            };
+            let inputs: Vec<_> = (0..n).map(&mut *read).collect();
+            Ok((0..n)
+                .map(|bit| {
+                    let value = lookup(context, builder, &inputs, |index| {
+                        (words[index] >> bit) & 1 == 1
+                    });
+                    (bit, value)
+                })
+                .collect())
+        }
+        // The operation applies to consecutive groups of bits
+        OpType::MultiBit => {
+            let inner = classical.op.as_ref().ok_or_else(|| missing("op"))?;
+            let n = count(&classical.n, "n")?;
+            let width = n_args / n.max(1);
+            check_op_arity(optype, n_args, n * width)?;
+            let mut writes = vec![];
+            for offset in (0..n).map(|group| group * width) {
+                let mut read_group = |i: usize| read(offset + i);
+                let group = &args[offset..offset + width];
+                let group_writes = emit_classical(context, builder, inner, group, &mut read_group)?;
+                writes.extend(
+                    group_writes
+                        .into_iter()
+                        .map(|(i, value)| (offset + i, value)),
+                );
+            }
+            Ok(writes)
+        }
+        _ => Err(CommandError::UnsupportedOp(optype.clone())),
+    }
+}
+
+/// The bit values of `classical`, as many as the entries of a table on
+/// `n_read` bits if given.
+fn bits<'a>(
+    classical: &'a Classical,
+    optype: &OpType,
+    n_read: Option<usize>,
+) -> Result<&'a [bool], CommandError> {
+    match classical.values.as_ref() {
+        Some(ClassicalValues::Bits(values))
+            if n_read.map_or(true, |n| table_len(n) == Some(values.len())) =>
+        {
+            Ok(values)
+        }
+        _ => Err(CommandError::MissingData(optype.clone(), "values")),
+    }
+}
+
+/// Number of entries of a table on `n` bits, `None` if it is too large.
+fn table_len(n: usize) -> Option<usize> {
+    u32::try_from(n).ok().and_then(|n| 1usize.checked_shl(n))
+}
+
+/// The `i64` whose bit `i` is `bits[i]`, of which there are at most 64.
+fn word<'ctx>(
+    context: &'ctx Context,
+    builder: &Builder<'ctx>,
+    bits: &[IntValue<'ctx>],
+) -> IntValue<'ctx> {
+    let i64_type = context.i64_type();
+    bits.iter()
+        .enumerate()
+        .fold(i64_type.const_zero(), |word, (i, &bit)| {
+            let bit = builder.build_int_z_extend(bit, i64_type, "bit");
+            let shifted =
+                builder.build_left_shift(bit, i64_type.const_int(i as u64, false), "shifted");
+            builder.build_or(word, shifted, "word")
+        })
+}
+
+/// The entry of `table` at the index whose bit `i` is `inputs[i]`.
+fn lookup<'ctx>(
+    context: &'ctx Context,
+    builder: &Builder<'ctx>,
+    inputs: &[IntValue<'ctx>],
+    table: impl Fn(usize) -> bool,
+) -> IntValue<'ctx> {
+    let index = word(context, builder, inputs);
+    let i64_type = context.i64_type();
+    (0..1usize << inputs.len())
+        .filter(|&entry| table(entry))
+        .fold(context.bool_type().const_zero(), |value, entry| {
+            let matches = builder.build_int_compare(
+                IntPredicate::EQ,
+                index,
+                i64_type.const_int(entry as u64, false),
+                "matches",
+            );
+            builder.build_or(value, matches, "lookup")
+        })
+}
+
+/// State for emitting the expression of a ClassicalExpBox, whose bits are
+/// read from the arguments `args` of the box with the same name.
+struct ExpEmitter<'a, 'ctx> {
+    context: &'ctx Context,
+    builder: &'a Builder<'ctx>,
+    args: &'a [Register],
+    read: &'a mut dyn FnMut(usize) -> IntValue<'ctx>,
+}
+
+impl<'a, 'ctx> ExpEmitter<'a, 'ctx> {
+    /// Value of `exp`, an `i1` for operations on bits and comparisons and an
+    /// `i64` for other operations on registers.
+    fn eval(&mut self, exp: &ClassicalExp) -> Result<IntValue<'ctx>, CommandError> {
+        let unsupported = || CommandError::UnsupportedExpression(exp.op.clone());
+        let (bitwise, name) = match exp.op.split_once('.') {
+            Some(("BitWiseOp", name)) => (true, name),
+            Some(("RegWiseOp", name)) => (false, name),
+            _ => return Err(unsupported()),
+        };
+        let operands = exp
+            .args
+            .iter()
+            .map(|arg| self.operand(arg, bitwise))
+            .collect::<Result<Vec<_>, _>>()?;
+        let int_type = if bitwise {
+            self.context.bool_type()
+        } else {
+            self.context.i64_type()
+        };
+        let builder = self.builder;
+        let compare = |predicate, a: IntValue<'ctx>, b: IntValue<'ctx>| {
+            builder.build_int_compare(predicate, a, b, "compare")
+        };
+        let value = match (name, &operands[..]) {
+            ("ZERO", []) => int_type.const_zero(),
+            ("ONE", []) => int_type.const_int(1, false),
+            ("NOT", &[a]) => builder.build_not(a, "not"),
+            ("NEG", &[a]) => builder.build_int_neg(a, "neg"),
+            ("AND", &[a, b]) => builder.build_and(a, b, "and"),
+            ("OR", &[a, b]) => builder.build_or(a, b, "or"),
+            ("XOR", &[a, b]) => builder.build_xor(a, b, "xor"),
+            ("EQ", &[a, b]) => compare(IntPredicate::EQ, a, b),
+            ("NEQ", &[a, b]) => compare(IntPredicate::NE, a, b),
+            ("LT", &[a, b]) => compare(IntPredicate::ULT, a, b),
+            ("GT", &[a, b]) => compare(IntPredicate::UGT, a, b),
+            ("LEQ", &[a, b]) => compare(IntPredicate::ULE, a, b),
+            ("GEQ", &[a, b]) => compare(IntPredicate::UGE, a, b),
+            ("ADD", &[a, b]) if !bitwise => builder.build_int_add(a, b, "add"),
+            ("SUB", &[a, b]) if !bitwise => builder.build_int_sub(a, b, "sub"),
+            ("MUL", &[a, b]) if !bitwise => builder.build_int_mul(a, b, "mul"),
+            ("DIV", &[a, b]) if !bitwise => self.div(a, b),
+            ("POW", &[a, b]) if !bitwise => self.pow(a, b),
+            ("LSH", &[a, b]) if !bitwise => self.shift(a, b, true),
+            ("RSH", &[a, b]) if !bitwise => self.shift(a, b, false),
+            _ => return Err(unsupported()),
+        };
+        Ok(value)
+    }
+
+    /// Value of an argument of an operation on bits, as an `i1`, or on
+    /// registers, as an `i64`.
+    fn operand(
+        &mut self,
+        arg: &ClassicalExpArg,
+        bitwise: bool,
+    ) -> Result<IntValue<'ctx>, CommandError> {
+        let i64_type = self.context.i64_type();
+        let value = match arg {
+            ClassicalExpArg::Constant(value) if bitwise => {
+                self.context.bool_type().const_int(value & 1, false)
+            }
+            ClassicalExpArg::Constant(value) => i64_type.const_int(*value, false),
+            ClassicalExpArg::Bit(bit) => self.bit(bit)?,
+            ClassicalExpArg::Register(register) => {
+                if register.size > 64 {
+                    return Err(CommandError::UnsupportedExpression(format!(
+                        "register {} of {} bits",
+                        register.name, register.size
+                    )));
+                }
+                let bits = (0..register.size as u64)
+                    .map(|i| self.bit(&Register(register.name.clone(), vec![i])))
+                    .collect::<Result<Vec<_>, _>>()?;
+                word(self.context, self.builder, &bits)
+            }
+            ClassicalExpArg::Exp(exp) => self.eval(exp)?,
+        };
+        let width = value.get_type().get_bit_width();
+        Ok(match (bitwise, width) {
+            (true, 1) | (false, 64) => value,
+            (true, _) => self
+                .builder
+                .build_int_truncate(value, self.context.bool_type(), "bit"),
+            (false, _) => self.builder.build_int_z_extend(value, i64_type, "word"),
+        })
+    }
+
+    /// Value of a bit of the expression, which is one of the arguments.
+    fn bit(&mut self, bit: &Register) -> Result<IntValue<'ctx>, CommandError> {
+        let position = self
+            .args
+            .iter()
+            .position(|arg| arg == bit)
+            .ok_or_else(|| CommandError::UnknownRegister(bit.clone()))?;
+        Ok((self.read)(position))
+    }
+
+    /// `a / b` rounded down, zero if `b` is.
+    fn div(&self, a: IntValue<'ctx>, b: IntValue<'ctx>) -> IntValue<'ctx> {
+        let (builder, zero) = (self.builder, self.context.i64_type().const_zero());
+        let by_zero = builder.build_int_compare(IntPredicate::EQ, b, zero, "by_zero");
+        let one = self.context.i64_type().const_int(1, false);
+        let divisor = builder
+            .build_select(by_zero, one, b, "divisor")
+            .into_int_value();
+        let quotient = builder.build_int_unsigned_div(a, divisor, "div");
+        builder
+            .build_select(by_zero, zero, quotient, "div")
+            .into_int_value()
+    }
+
+    /// `a` raised to the power `b` by squaring, over the bits of `b` if it
+    /// is a constant and all 64 otherwise.
+    fn pow(&self, a: IntValue<'ctx>, b: IntValue<'ctx>) -> IntValue<'ctx> {
+        let (builder, i64_type) = (self.builder, self.context.i64_type());
+        let constant = b.get_zero_extended_constant();
+        let n_bits = constant.map_or(64, |exponent| 64 - exponent.leading_zeros());
+        let (mut value, mut power) = (i64_type.const_int(1, false), a);
+        for i in 0..n_bits {
+            let product = builder.build_int_mul(value, power, "pow");
+            value = match constant {
+                Some(exponent) if (exponent >> i) & 1 == 1 => product,
+                Some(_) => value,
+                None => {
+                    let shifted = builder.build_right_shift(
+                        b,
+                        i64_type.const_int(i as u64, false),
+                        false,
+                        "",
+                    );
+                    let bit =
+                        builder.build_int_truncate(shifted, self.context.bool_type(), "exponent");
+                    builder
+                        .build_select(bit, product, value, "pow")
+                        .into_int_value()
+                }
+            };
+            if i + 1 < n_bits {
+                power = builder.build_int_mul(power, power, "power");
+            }
+        }
+        value
+    }
+
+    /// `a` shifted left or right by `b` bits, zero if `b` is at least 64.
+    fn shift(&self, a: IntValue<'ctx>, b: IntValue<'ctx>, left: bool) -> IntValue<'ctx> {
+        let (builder, i64_type) = (self.builder, self.context.i64_type());
+        let shifted = if left {
+            builder.build_left_shift(a, b, "shift")
+        } else {
+            builder.build_right_shift(a, b, false, "shift")
+        };
+        let overflow = builder.build_int_compare(
+            IntPredicate::UGE,
+            b,
+            i64_type.const_int(64, false),
+            "overflow",
+        );
+        builder
+            .build_select(overflow, i64_type.const_zero(), shifted, "shift")
+            .into_int_value()
+    }
+
+    /// The writes of `value` to the bits `outputs`, the first at position
+    /// `offset`: a bit value to the single output, and bit `i` of a register
+    /// value to the output `name[i]`.
+    fn write(
+        &self,
+        exp: &ClassicalExp,
+        value: IntValue<'ctx>,
+        offset: usize,
+        outputs: &[Register],
+    ) -> Result<Vec<(usize, IntValue<'ctx>)>, CommandError> {
+        if value.get_type().get_bit_width() == 1 {
+            return match outputs {
+                [_] => Ok(vec![(offset, value)]),
+                _ => Err(CommandError::UnsupportedExpression(exp.op.clone())),
+            };
+        }
+        let (builder, i64_type) = (self.builder, self.context.i64_type());
+        Ok(outputs
+            .iter()
+            .enumerate()
+            .map(|(i, Register(_, index))| {
+                let shift = index.first().copied().unwrap_or(0);
+                let bit = if shift < 64 {
+                    let shifted = builder.build_right_shift(
+                        value,
+                        i64_type.const_int(shift, false),
+                        false,
+                        "",
+                    );
+                    builder.build_int_truncate(shifted, self.context.bool_type(), "bit")
+                } else {
+                    self.context.bool_type().const_zero()
+                };
+                (offset + i, bit)
+            })
+            .collect())
+    }
+}
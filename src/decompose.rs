@@ -0,0 +1,310 @@
+use crate::circuit::OpType;
+
+/// A gate with a native QIS function, acting on positions into the
+/// arguments of the command it was decomposed from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gate {
+    pub op_type: OpType,
+    // Angles in half-turns, as in pytket
+    pub params: Vec<f64>,
+    pub args: Vec<usize>,
+}
+
+impl Gate {
+    pub(crate) fn new(op_type: OpType, params: Vec<f64>, args: Vec<usize>) -> Self {
+        Gate {
+            op_type,
+            params,
+            args,
+        }
+    }
+}
+
+/// Number of qubits and parameters of a gate, `None` for operations which
+/// are not plain gates (measurements, boxes, conditionals).
+pub fn gate_signature(op_type: &OpType) -> Option<(usize, usize)> {
+    match op_type {
+        OpType::H
+        | OpType::X
+        | OpType::Y
+        | OpType::Z
+        | OpType::S
+        | OpType::Sdg
+        | OpType::T
+        | OpType::Tdg
+        | OpType::V
+        | OpType::Vdg
+        | OpType::SX
+        | OpType::SXdg
+        | OpType::Reset => Some((1, 0)),
+        OpType::Rx | OpType::Ry | OpType::Rz | OpType::U1 => Some((1, 1)),
+        OpType::U2 | OpType::PhasedX => Some((1, 2)),
+        OpType::U3 | OpType::TK1 => Some((1, 3)),
+        OpType::CX | OpType::CY | OpType::CZ | OpType::SWAP | OpType::ZZMax | OpType::ECR => {
+            Some((2, 0))
+        }
+        OpType::CRz | OpType::CU1 | OpType::ZZPhase | OpType::XXPhase | OpType::YYPhase => {
+            Some((2, 1))
+        }
+        OpType::CCX => Some((3, 0)),
+        _ => None,
+    }
+}
+
+/// Whether the gate is emitted directly as a QIS call.
+pub fn is_native(op_type: &OpType) -> bool {
+    matches!(
+        op_type,
+        OpType::H
+            | OpType::X
+            | OpType::Y
+            | OpType::Z
+            | OpType::S
+            | OpType::Sdg
+            | OpType::T
+            | OpType::Tdg
+            | OpType::Rx
+            | OpType::Ry
+            | OpType::Rz
+            | OpType::CX
+            | OpType::CZ
+            | OpType::SWAP
+            | OpType::CCX
+            | OpType::Reset
+    )
+}
+
+/// Decompose a gate into native gates and the global phase, in half-turns,
+/// by which they differ from it. Returns `None` for native gates and gates
+/// without a decomposition.
+///
+/// `params` must match the gate signature.
+pub fn decompose(op_type: &OpType, params: &[f64]) -> Option<(Vec<Gate>, f64)> {
+    let rz = |angle| Gate::new(OpType::Rz, vec![angle], vec![0]);
+    let rx = |angle| Gate::new(OpType::Rx, vec![angle], vec![0]);
+    let ry = |angle| Gate::new(OpType::Ry, vec![angle], vec![0]);
+    let on =
+        |op_type: OpType, params: Vec<f64>, qubit: usize| Gate::new(op_type, params, vec![qubit]);
+    let cx = || Gate::new(OpType::CX, vec![], vec![0, 1]);
+    // exp(-iπα/2 Z⊗Z)
+    let zz_phase = |alpha: f64| vec![cx(), on(OpType::Rz, vec![alpha], 1), cx()];
+    // Conjugate a two-qubit gate by the same single-qubit gate on both qubits
+    let conjugate = |before: (OpType, Vec<f64>), inner: Vec<Gate>, after: (OpType, Vec<f64>)| {
+        let mut gates = vec![
+            on(before.0.clone(), before.1.clone(), 0),
+            on(before.0, before.1, 1),
+        ];
+        gates.extend(inner);
+        gates.push(on(after.0.clone(), after.1.clone(), 0));
+        gates.push(on(after.0, after.1, 1));
+        gates
+    };
+
+    let gates = match (op_type, params) {
+        (OpType::V, []) => (vec![rx(0.5)], 0.0),
+        (OpType::Vdg, []) => (vec![rx(-0.5)], 0.0),
+        (OpType::SX, []) => (vec![rx(0.5)], 0.25),
+        (OpType::SXdg, []) => (vec![rx(-0.5)], -0.25),
+        (OpType::U1, [lambda]) => (vec![rz(*lambda)], lambda / 2.0),
+        // U3(θ, φ, λ) = e^{iπ(φ+λ)/2} Rz(φ)Ry(θ)Rz(λ)
+        (OpType::U2, [phi, lambda]) => (vec![rz(*lambda), ry(0.5), rz(*phi)], (phi + lambda) / 2.0),
+        (OpType::U3, [theta, phi, lambda]) => (
+            vec![rz(*lambda), ry(*theta), rz(*phi)],
+            (phi + lambda) / 2.0,
+        ),
+        // TK1(α, β, γ) = Rz(α)Rx(β)Rz(γ)
+        (OpType::TK1, [alpha, beta, gamma]) => (vec![rz(*gamma), rx(*beta), rz(*alpha)], 0.0),
+        // PhasedX(θ, φ) = Rz(φ)Rx(θ)Rz(-φ)
+        (OpType::PhasedX, [theta, phi]) => (vec![rz(-phi), rx(*theta), rz(*phi)], 0.0),
+        (OpType::CY, []) => (
+            vec![on(OpType::Sdg, vec![], 1), cx(), on(OpType::S, vec![], 1)],
+            0.0,
+        ),
+        (OpType::CRz, [theta]) => (
+            vec![
+                on(OpType::Rz, vec![theta / 2.0], 1),
+                cx(),
+                on(OpType::Rz, vec![-theta / 2.0], 1),
+                cx(),
+            ],
+            0.0,
+        ),
+        // CU1(λ) = e^{iπλ/4} CRz(λ) with an Rz(λ/2) on the control
+        (OpType::CU1, [lambda]) => (
+            vec![
+                on(OpType::Rz, vec![lambda / 2.0], 0),
+                on(OpType::Rz, vec![lambda / 2.0], 1),
+                cx(),
+                on(OpType::Rz, vec![-lambda / 2.0], 1),
+                cx(),
+            ],
+            lambda / 4.0,
+        ),
+        (OpType::ZZPhase, [alpha]) => (zz_phase(*alpha), 0.0),
+        (OpType::ZZMax, []) => (zz_phase(0.5), 0.0),
+        (OpType::XXPhase, [alpha]) => (
+            conjugate((OpType::H, vec![]), zz_phase(*alpha), (OpType::H, vec![])),
+            0.0,
+        ),
+        (OpType::YYPhase, [alpha]) => (
+            conjugate(
+                (OpType::Rx, vec![0.5]),
+                zz_phase(*alpha),
+                (OpType::Rx, vec![-0.5]),
+            ),
+            0.0,
+        ),
+        // ECR = X₀ exp(-iπ/4 Z⊗X)
+        (OpType::ECR, []) => (
+            vec![
+                on(OpType::H, vec![], 1),
+                cx(),
+                on(OpType::Rz, vec![0.5], 1),
+                cx(),
+                on(OpType::H, vec![], 1),
+                on(OpType::X, vec![], 0),
+            ],
+            0.0,
+        ),
+        _ => return None,
+    };
+    Some(gates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesis::{gates_unitary, Matrix};
+    use num_complex::Complex64;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_native_gates_are_not_decomposed() {
+        for op_type in [
+            OpType::H,
+            OpType::Sdg,
+            OpType::Rz,
+            OpType::CX,
+            OpType::CZ,
+            OpType::SWAP,
+            OpType::CCX,
+            OpType::Reset,
+        ] {
+            assert!(is_native(&op_type));
+            let (_, n_params) = gate_signature(&op_type).unwrap();
+            assert_eq!(decompose(&op_type, &vec![0.5; n_params]), None);
+        }
+    }
+
+    #[test]
+    fn test_decompositions_are_native() {
+        for op_type in [
+            OpType::V,
+            OpType::Vdg,
+            OpType::SX,
+            OpType::SXdg,
+            OpType::U1,
+            OpType::U2,
+            OpType::U3,
+            OpType::TK1,
+            OpType::PhasedX,
+            OpType::CY,
+            OpType::CRz,
+            OpType::CU1,
+            OpType::ZZPhase,
+            OpType::ZZMax,
+            OpType::XXPhase,
+            OpType::YYPhase,
+            OpType::ECR,
+        ] {
+            let (n_qubits, n_params) = gate_signature(&op_type).unwrap();
+            let (gates, _) = decompose(&op_type, &vec![0.25; n_params]).unwrap();
+            for gate in gates {
+                assert!(is_native(&gate.op_type));
+                assert!(gate.args.iter().all(|&arg| arg < n_qubits));
+            }
+        }
+    }
+
+    #[test]
+    fn test_u3_order() {
+        let (gates, phase) = decompose(&OpType::U3, &[0.1, 0.2, 0.3]).unwrap();
+        assert_eq!(
+            gates,
+            vec![
+                Gate::new(OpType::Rz, vec![0.3], vec![0]),
+                Gate::new(OpType::Ry, vec![0.1], vec![0]),
+                Gate::new(OpType::Rz, vec![0.2], vec![0]),
+            ]
+        );
+        assert!((phase - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_exact_phases() {
+        let c = Complex64::new;
+        let e = |t: f64| Complex64::from_polar(1.0, t * PI);
+        let (zero, one) = (c(0.0, 0.0), c(1.0, 0.0));
+        let half = c(0.5, 0.0);
+        let cases: Vec<(OpType, Vec<f64>, Matrix)> = vec![
+            (
+                OpType::U1,
+                vec![0.3],
+                vec![vec![one, zero], vec![zero, e(0.3)]],
+            ),
+            (
+                OpType::SX,
+                vec![],
+                vec![
+                    vec![half * c(1.0, 1.0), half * c(1.0, -1.0)],
+                    vec![half * c(1.0, -1.0), half * c(1.0, 1.0)],
+                ],
+            ),
+            (
+                OpType::SXdg,
+                vec![],
+                vec![
+                    vec![half * c(1.0, -1.0), half * c(1.0, 1.0)],
+                    vec![half * c(1.0, 1.0), half * c(1.0, -1.0)],
+                ],
+            ),
+            (
+                OpType::U3,
+                vec![0.1, 0.2, 0.3],
+                vec![
+                    vec![one * (0.05 * PI).cos(), -e(0.3) * (0.05 * PI).sin()],
+                    vec![e(0.2) * (0.05 * PI).sin(), e(0.5) * (0.05 * PI).cos()],
+                ],
+            ),
+            (
+                OpType::CU1,
+                vec![0.3],
+                vec![
+                    vec![one, zero, zero, zero],
+                    vec![zero, one, zero, zero],
+                    vec![zero, zero, one, zero],
+                    vec![zero, zero, zero, e(0.3)],
+                ],
+            ),
+            (
+                OpType::CY,
+                vec![],
+                vec![
+                    vec![one, zero, zero, zero],
+                    vec![zero, one, zero, zero],
+                    vec![zero, zero, zero, c(0.0, -1.0)],
+                    vec![zero, zero, c(0.0, 1.0), zero],
+                ],
+            ),
+        ];
+        for (op_type, params, expected) in cases {
+            let (gates, phase) = decompose(&op_type, &params).unwrap();
+            let actual = gates_unitary(&gates, expected.len().trailing_zeros() as usize);
+            for (row, expected_row) in actual.iter().zip(&expected) {
+                for (x, y) in row.iter().zip(expected_row) {
+                    assert!((x * e(phase) - y).norm() < 1e-9, "{:?}", op_type);
+                }
+            }
+        }
+    }
+}
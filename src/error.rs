@@ -0,0 +1,173 @@
+use std::fmt;
+
+use crate::circuit::{OpType, Register};
+use crate::generate::Profile;
+use crate::params::ParamError;
+
+/// Reasons a single command cannot be converted to QIR.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// The operation has no lowering to QIR.
+    UnsupportedOp(OpType),
+    /// A parameter is not a valid expression.
+    BadParameter(String),
+    /// A parameter has a free symbol without a value.
+    UnboundSymbol(String),
+    /// An argument is not one of the circuit's qubits or bits.
+    UnknownRegister(Register),
+    /// The command has the wrong number of arguments.
+    Arity {
+        op_type: OpType,
+        expected: usize,
+        found: usize,
+    },
+    /// The command has the wrong number of parameters.
+    ParamArity {
+        op_type: OpType,
+        expected: usize,
+        found: usize,
+    },
+    /// A field the operation relies on, e.g. its box or condition, is absent.
+    MissingData(OpType, &'static str),
+    /// An operation or register of a ClassicalExpBox expression that has no
+    /// lowering.
+    UnsupportedExpression(String),
+    /// A classical condition that cannot be expressed.
+    UnsupportedCondition { width: u32, value: u32 },
+    /// The matrix of a unitary box is not unitary.
+    NotUnitary(OpType),
+    /// The matrix of an exponential box is not Hermitian.
+    NotHermitian(OpType),
+    /// The operation needs more than the profile offers.
+    NotInProfile(OpType, Profile),
+    /// A qubit is used after being measured, which the base profile forbids.
+    MeasuredQubit(Register),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnsupportedOp(op_type) => {
+                write!(f, "unsupported operation {:?}", op_type)
+            }
+            CommandError::BadParameter(param) => write!(f, "invalid parameter {:?}", param),
+            CommandError::UnboundSymbol(symbol) => write!(f, "no value for symbol {}", symbol),
+            CommandError::UnknownRegister(Register(name, index)) => {
+                write!(f, "unknown register {}{:?}", name, index)
+            }
+            CommandError::Arity {
+                op_type,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:?} expects {} arguments, found {}",
+                op_type, expected, found
+            ),
+            CommandError::ParamArity {
+                op_type,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:?} expects {} parameters, found {}",
+                op_type, expected, found
+            ),
+            CommandError::MissingData(op_type, field) => {
+                write!(f, "{:?} is missing its {}", op_type, field)
+            }
+            CommandError::UnsupportedExpression(exp) => {
+                write!(f, "unsupported classical expression {}", exp)
+            }
+            CommandError::UnsupportedCondition { width, value } => {
+                write!(f, "unsupported condition {} on {} bits", value, width)
+            }
+            CommandError::NotUnitary(op_type) => write!(f, "{:?} matrix is not unitary", op_type),
+            CommandError::NotHermitian(op_type) => {
+                write!(f, "{:?} matrix is not Hermitian", op_type)
+            }
+            CommandError::NotInProfile(op_type, profile) => {
+                write!(
+                    f,
+                    "{:?} is not supported by the {:?} profile",
+                    op_type, profile
+                )
+            }
+            CommandError::MeasuredQubit(Register(name, index)) => {
+                write!(f, "qubit {}{:?} is used after being measured", name, index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Errors raised while converting a pytket circuit to QIR.
+#[derive(Debug)]
+pub enum TketQirError {
+    /// A command of the circuit could not be converted.
+    Command {
+        /// Position of the command in `Circuit::commands`, its length for the
+        /// SWAPs that apply the implicit permutation.
+        index: usize,
+        opgroup: Option<String>,
+        source: CommandError,
+    },
+    /// The input is not a valid pytket circuit.
+    Json(serde_json::Error),
+    /// qirlib failed to build or verify the module.
+    Codegen(String),
+    /// The global phase of the circuit is not a valid expression or has an
+    /// unbound symbol.
+    Phase(ParamError),
+    /// The implicit permutation maps a qubit the circuit does not have, or
+    /// two qubits to the same one.
+    Permutation(Register),
+    /// Relabelling was asked for in the full profile, whose qubits have no
+    /// static addresses.
+    Relabel,
+}
+
+impl fmt::Display for TketQirError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TketQirError::Command {
+                index,
+                opgroup: Some(opgroup),
+                source,
+            } => write!(f, "command {} (opgroup {}): {}", index, opgroup, source),
+            TketQirError::Command {
+                index,
+                opgroup: None,
+                source,
+            } => write!(f, "command {}: {}", index, source),
+            TketQirError::Json(err) => write!(f, "invalid circuit: {}", err),
+            TketQirError::Codegen(err) => write!(f, "code generation failed: {}", err),
+            TketQirError::Phase(err) => write!(f, "invalid global phase: {}", err),
+            TketQirError::Permutation(Register(name, index)) => {
+                write!(f, "invalid implicit permutation of {}{:?}", name, index)
+            }
+            TketQirError::Relabel => write!(
+                f,
+                "qubits cannot be relabelled in the full profile, which allocates them at runtime"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TketQirError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TketQirError::Command { source, .. } => Some(source),
+            TketQirError::Json(err) => Some(err),
+            TketQirError::Phase(err) => Some(err),
+            TketQirError::Codegen(_) | TketQirError::Permutation(_) | TketQirError::Relabel => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for TketQirError {
+    fn from(err: serde_json::Error) -> Self {
+        TketQirError::Json(err)
+    }
+}
@@ -1,21 +1,204 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 
+use inkwell::attributes::AttributeLoc;
+use inkwell::context::Context;
+use inkwell::memory_buffer::MemoryBuffer;
+use inkwell::module::Linkage;
+use inkwell::types::{FunctionType, PointerType};
 use inkwell::values::{
-    BasicMetadataValueEnum, BasicValueEnum, FunctionValue, IntValue, PointerValue,
+    BasicMetadataValueEnum, BasicValue, BasicValueEnum, FunctionValue, IntValue, PointerValue,
 };
+use inkwell::AddressSpace;
 use qirlib::{
     codegen::CodeGenerator,
     generation::{
+        emit::ir,
         interop::{ClassicalRegister, CodeGenModel, QuantumRegister},
         qir::instructions::{controlled, get_qubit, measure},
     },
 };
 
-use crate::circuit::{BoxID, Circuit, Command, Conditional, OpBox, OpType, Operation, Register};
+use crate::base::circuit_to_base_qir;
+use crate::circuit::{
+    BoxID, Circuit, Command, Conditional, OpBox, OpType, Operation, Permutation, Register,
+};
+use crate::classical::{emit_classical, is_classical};
+use crate::decompose::{decompose, gate_signature, Gate};
+use crate::error::{CommandError, TketQirError};
+use crate::inline::{inline_circbox, inlined_phases};
+use crate::layout::{QirName, RegisterLayout};
+use crate::params::{eval_param, half_turns_to_radians, Bindings, ParamError};
+use crate::synthesis::{exp_box, pauli_exp, phase_poly, unitary1q, unitary2q};
+
+/// Options for [`circuit_to_qir_with`].
+#[derive(Clone, Debug, Default)]
+pub struct QirOptions {
+    /// Values substituted for the free symbols of gate parameters.
+    pub bindings: Bindings,
+    /// Emit each distinct `CircBox` as an internal function called at every
+    /// use, rather than inlining it. Boxes with bits are always inlined, as
+    /// measurement results are not passed between functions.
+    pub box_functions: bool,
+    /// How the circuit's implicit qubit permutation is applied, by default
+    /// [`PermutationMode::Swaps`] in the full profile and
+    /// [`PermutationMode::Relabel`] in the others.
+    pub permutation: Option<PermutationMode>,
+    /// The QIR profile of the output.
+    pub profile: Profile,
+}
+
+/// QIR profile, i.e. the subset of QIR the output uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// The full runtime, with dynamically allocated qubits and result
+    /// registers, branches on results and arrays of controls.
+    #[default]
+    Full,
+    /// Static qubit and result addresses, no branches, and no use of a
+    /// qubit after it is measured. Results are reported at the end with
+    /// `__quantum__rt__result_record_output`.
+    Base,
+    /// Static addresses as in the base profile, with measured qubits reused,
+    /// results read as booleans with `__quantum__qis__read_result__body`,
+    /// branches and integer computations on them. Bits are reported at the
+    /// end with `__quantum__rt__bool_record_output`.
+    Adaptive,
+}
+
+/// How to apply the implicit permutation pytket leaves after eliding SWAPs,
+/// under which the state of qubit `a` ends up on qubit `b` for each pair
+/// `(a, b)`. Either way QIR qubit `b` holds that state at the end,
+/// measurement results being unaffected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PermutationMode {
+    /// Give the qubit of `a` the static address of `b`, at no cost in
+    /// gates. Not available in the full profile, which allocates its qubits
+    /// at runtime.
+    Relabel,
+    /// Keep the names and append the SWAPs pytket elided.
+    Swaps,
+}
+
+/// Entry-point attribute holding the global phase of the circuit, in
+/// half-turns, when it is not zero.
+pub const GLOBAL_PHASE_ATTRIBUTE: &str = "tket_global_phase";
+
+/// Convert a pytket circuit to textual QIR.
+pub fn circuit_to_qir(circ: &Circuit) -> Result<String, TketQirError> {
+    circuit_to_qir_with(circ, &QirOptions::default())
+}
 
-impl CodeGenModel for Circuit {
+/// Convert a pytket circuit to textual QIR with the given options.
+pub fn circuit_to_qir_with(circ: &Circuit, options: &QirOptions) -> Result<String, TketQirError> {
+    let layout = RegisterLayout::new(circ);
+    let targets = permutation_targets(circ, &layout)?;
+    let phase = eval_param(&circ.phase, &options.bindings).map_err(TketQirError::Phase)?;
+    let permutation = match (options.profile, options.permutation) {
+        // Qubits allocated at runtime have no address to relabel, and naming
+        // them differently moves no state
+        (Profile::Full, Some(PermutationMode::Relabel)) => return Err(TketQirError::Relabel),
+        (_, Some(permutation)) => permutation,
+        (Profile::Full, None) => PermutationMode::Swaps,
+        (_, None) => PermutationMode::Relabel,
+    };
+    if options.profile != Profile::Full {
+        return circuit_to_base_qir(circ, options, permutation, &layout, &targets, phase);
+    }
+    let swaps = permutation_swaps(&targets);
+    let model = CircuitModel {
+        circuit: circ,
+        options,
+        layout,
+        swaps,
+        phase,
+        error: RefCell::new(None),
+    };
+    let ir = ir(&model);
+    // A failed command leaves the module half built, so report it first.
+    if let Some(err) = model.error.into_inner() {
+        return Err(err);
+    }
+    ir.map_err(TketQirError::Codegen)
+}
+
+/// Convert a pytket circuit serialised as JSON to textual QIR.
+pub fn json_to_qir(circ_s: &str) -> Result<String, TketQirError> {
+    circuit_to_qir(&serde_json::from_str(circ_s)?)
+}
+
+/// Flat index of the qubit each qubit of the circuit ends up on under its
+/// implicit permutation.
+fn permutation_targets(
+    circ: &Circuit,
+    layout: &RegisterLayout,
+) -> Result<Vec<usize>, TketQirError> {
+    let qubits = &layout.qubits;
+    let mut targets: Vec<usize> = (0..qubits.len()).collect();
+    for Permutation(a, b) in &circ.implicit_permutation {
+        let index = |unit: &Register| {
+            qubits
+                .index(unit)
+                .ok_or_else(|| TketQirError::Permutation(unit.clone()))
+        };
+        targets[index(a)?] = index(b)?;
+    }
+    let mut seen = vec![false; targets.len()];
+    for (i, &target) in targets.iter().enumerate() {
+        if std::mem::replace(&mut seen[target], true) {
+            let unit = qubits.unit(i).expect("targets are flat indices");
+            return Err(TketQirError::Permutation(unit.clone()));
+        }
+    }
+    Ok(targets)
+}
+
+/// Transpositions, of flat qubit indices, moving the state of each qubit `i`
+/// to `targets[i]` when applied in order.
+pub(crate) fn permutation_swaps(targets: &[usize]) -> Vec<(usize, usize)> {
+    let mut sources = vec![0; targets.len()];
+    for (i, &target) in targets.iter().enumerate() {
+        sources[target] = i;
+    }
+    // `position[i]` is where the state of qubit `i` currently is and
+    // `occupant[j]` the qubit whose state is on `j`
+    let mut position: Vec<usize> = (0..targets.len()).collect();
+    let mut occupant = position.clone();
+    let mut swaps = vec![];
+    for (target, &source) in sources.iter().enumerate() {
+        let current = position[source];
+        if current != target {
+            swaps.push((current, target));
+            let displaced = occupant[target];
+            occupant[current] = displaced;
+            position[displaced] = current;
+            occupant[target] = source;
+            position[source] = target;
+        }
+    }
+    swaps
+}
+
+/// Code generation model of a circuit. `CodeGenModel::write_instructions`
+/// cannot fail, so the first failing command is kept aside in `error`.
+struct CircuitModel<'a> {
+    circuit: &'a Circuit,
+    options: &'a QirOptions,
+    layout: RegisterLayout,
+    // SWAPs, of flat qubit indices, appended to apply the implicit permutation
+    swaps: Vec<(usize, usize)>,
+    // Global phase of the circuit itself, in half-turns
+    phase: f64,
+    error: RefCell<Option<TketQirError>>,
+}
+
+impl CodeGenModel for CircuitModel<'_> {
     fn name(&self) -> String {
-        self.name.clone().unwrap_or("tket_circuit".to_string())
+        self.circuit
+            .name
+            .as_ref()
+            .cloned()
+            .unwrap_or_else(|| "tket_circuit".to_string())
     }
 
     fn number_of_registers(&self) -> usize {
@@ -23,24 +206,20 @@ impl CodeGenModel for Circuit {
     }
 
     fn registers(&self) -> Vec<ClassicalRegister> {
-        let mut regmap = HashMap::new();
-        for bit in self.bits.iter() {
-            if let Some(size) = regmap.get_mut(&bit.0) {
-                *size = std::cmp::max(*size, bit.1[0] + 1);
-            } else {
-                regmap.insert(bit.0.clone(), bit.1[0] + 1);
-            }
-        }
-        regmap
+        self.layout
+            .bits
+            .registers()
             .into_iter()
             .map(|(name, size)| ClassicalRegister::new(name, size))
             .collect()
     }
 
     fn qubits(&self) -> Vec<QuantumRegister> {
-        self.qubits
+        self.layout
+            .qubits
+            .names()
             .iter()
-            .map(|qb| QuantumRegister::new(qb.0.clone(), qb.1[0] as u64))
+            .map(|name| QuantumRegister::new(name.register.clone(), name.index))
             .collect()
     }
 
@@ -57,7 +236,7 @@ impl CodeGenModel for Circuit {
     // }
 
     fn number_of_qubits(&self) -> usize {
-        self.qubits.len()
+        self.circuit.qubits.len()
     }
 
     fn write_instructions<'ctx>(
@@ -67,229 +246,987 @@ impl CodeGenModel for Circuit {
         registers: &mut HashMap<String, Option<PointerValue<'ctx>>>,
         entry_point: FunctionValue,
     ) {
-        for com in &self.commands {
-            emit(generator, com, qubits, registers, entry_point);
+        let mut box_functions = HashMap::new();
+        let mut phase = self.phase;
+        let mut emitter = Emitter {
+            generator,
+            qubits,
+            registers,
+            entry_point,
+            options: self.options,
+            layout: &self.layout,
+            box_functions: &mut box_functions,
+            phase: &mut phase,
+        };
+        for (index, com) in self.circuit.commands.iter().enumerate() {
+            if let Err(source) = emitter.emit(com) {
+                *self.error.borrow_mut() = Some(TketQirError::Command {
+                    index,
+                    opgroup: com.opgroup.as_ref().cloned(),
+                    source,
+                });
+                return;
+            }
+        }
+        let swap = [Gate::new(OpType::SWAP, vec![], vec![0, 1])];
+        for &(a, b) in &self.swaps {
+            let qubits = &self.layout.qubits;
+            let args = [a, b].map(|i| qubits.unit(i).expect("swaps are of flat indices").clone());
+            emitter
+                .emit_gates(&swap, &args)
+                .expect("the circuit's qubits are laid out");
+        }
+
+        let phase = phase.rem_euclid(2.0);
+        if phase != 0.0 {
+            let attribute = generator
+                .context
+                .create_string_attribute(GLOBAL_PHASE_ATTRIBUTE, &phase.to_string());
+            entry_point.add_attribute(AttributeLoc::Function, attribute);
         }
     }
 }
 
-pub(crate) fn emit<'ctx>(
-    generator: &CodeGenerator<'ctx>,
-    com: &Command,
-    qubits: &HashMap<String, BasicValueEnum<'ctx>>,
-    registers: &mut HashMap<String, Option<PointerValue<'ctx>>>,
-    entry_point: FunctionValue,
-) {
-    let qb_name = |reg: &Register| format!("{}{}", &reg.0[..], reg.1[0]);
+pub(crate) fn check_arity(com: &Command, expected: usize) -> Result<(), CommandError> {
+    check_op_arity(&com.op.op_type, com.args.len(), expected)
+}
 
-    let find_qubit = |reg: &Register| get_qubit(&qb_name(reg), qubits);
+pub(crate) fn check_op_arity(
+    op_type: &OpType,
+    found: usize,
+    expected: usize,
+) -> Result<(), CommandError> {
+    if found == expected {
+        Ok(())
+    } else {
+        Err(CommandError::Arity {
+            op_type: op_type.clone(),
+            expected,
+            found,
+        })
+    }
+}
 
-    let optype = &com.op.op_type;
-    let params: Option<Vec<f64>> = com.op.params.as_ref().map(|params| {
-        params
+/// State for emitting the commands of a circuit into its entry point.
+struct Emitter<'a, 'ctx> {
+    generator: &'a CodeGenerator<'ctx>,
+    qubits: &'a HashMap<String, BasicValueEnum<'ctx>>,
+    registers: &'a mut HashMap<String, Option<PointerValue<'ctx>>>,
+    // The entry point need not share the context lifetime, see `write_instructions`.
+    entry_point: FunctionValue<'a>,
+    options: &'a QirOptions,
+    layout: &'a RegisterLayout,
+    // Functions emitted for `CircBox`es, see `QirOptions::box_functions`,
+    // with their global phase
+    box_functions: &'a mut HashMap<BoxID, (FunctionValue<'ctx>, f64)>,
+    // Global phase, in half-turns, of the commands emitted so far
+    phase: &'a mut f64,
+}
+
+impl<'a, 'ctx> Emitter<'a, 'ctx> {
+    fn find_qubit(&self, reg: &Register) -> Result<BasicValueEnum<'ctx>, CommandError> {
+        let name = self
+            .layout
+            .qubits
+            .name(reg)
+            .ok_or_else(|| CommandError::UnknownRegister(reg.clone()))?;
+        Ok(get_qubit(&name.key(), self.qubits))
+    }
+
+    fn bit_name(&self, reg: &Register) -> Result<String, CommandError> {
+        self.layout
+            .bits
+            .name(reg)
+            .map(QirName::key)
+            .ok_or_else(|| CommandError::UnknownRegister(reg.clone()))
+    }
+
+    fn param(&self, param: &str) -> Result<f64, CommandError> {
+        eval(param, &self.options.bindings)
+    }
+
+    /// Sum of phases given as Symengine Exprs.
+    fn phases(&self, phases: &[String]) -> Result<f64, CommandError> {
+        phases.iter().map(|phase| self.param(phase)).sum()
+    }
+
+    fn emit(&mut self, com: &Command) -> Result<(), CommandError> {
+        let optype = &com.op.op_type;
+        match optype {
+            OpType::Measure => {
+                check_arity(com, 2)?;
+                let qubit = self.layout.qubits.name(&com.args[0]);
+                let qubit = qubit
+                    .ok_or_else(|| CommandError::UnknownRegister(com.args[0].clone()))?
+                    .key();
+                let bit = self.bit_name(&com.args[1])?;
+                measure(self.generator, &qubit, &bit, self.qubits, self.registers);
+            }
+            OpType::Conditional => {
+                let (conditional, condition_bits, commands) = conditional_commands(com)?;
+                let condition_bits = condition_bits
+                    .iter()
+                    .map(|bit| self.bit_name(bit))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.emit_if(conditional, &condition_bits, &commands)?;
+            }
+            OpType::CircBox => match com.op.op_box.as_ref() {
+                Some(OpBox::CircBox { id, circuit, .. })
+                    if self.options.box_functions && circuit.bits.is_empty() =>
+                {
+                    check_arity(com, circuit.qubits.len())?;
+                    let args = com
+                        .args
+                        .iter()
+                        .map(|reg| self.find_qubit(reg).map(BasicMetadataValueEnum::from))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let (function, phase) = self.box_function(id, circuit)?;
+                    self.generator.emit_void_call(function, &args);
+                    *self.phase += phase;
+                }
+                Some(OpBox::CircBox { circuit, .. }) => {
+                    for inner in inline_circbox(circuit, &com.args)? {
+                        self.emit(&inner)?;
+                    }
+                    let phase = self.phases(&inlined_phases(circuit))?;
+                    *self.phase += phase;
+                }
+                _ => return Err(CommandError::MissingData(optype.clone(), "box")),
+            },
+            OpType::CustomGate => {
+                let (options, commands, phases) = self.custom_gate(&com.op, &com.args)?;
+                let mut box_functions = HashMap::new();
+                let mut emitter = self.scoped(&options, &mut box_functions);
+                for inner in &commands {
+                    emitter.emit(inner)?;
+                }
+                let phase = emitter.phases(&phases)?;
+                *emitter.phase += phase;
+            }
+            // Barriers only constrain compilation
+            OpType::Barrier => {}
+            _ if is_classical(optype) => self.emit_classical(&com.op, &com.args)?,
+            OpType::QControlBox => match com.op.op_box.as_ref() {
+                Some(OpBox::QControlBox {
+                    n_controls,
+                    op,
+                    control_state,
+                    ..
+                }) => {
+                    let n_controls = *n_controls as usize;
+                    let state = control_state.as_ref().copied();
+                    self.emit_controlled(&[], n_controls, state, op, &com.args)?;
+                }
+                _ => return Err(CommandError::MissingData(optype.clone(), "box")),
+            },
+            _ => {
+                let (gates, phase) = self.op_gates(&com.op, com.args.len())?;
+                self.emit_gates(&gates, &com.args)?;
+                *self.phase += phase;
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit a classical operation on the results of `args`, compared with
+    /// `One` to read them and set to `Zero` or `One` when written.
+    fn emit_classical(&mut self, op: &Operation, args: &[Register]) -> Result<(), CommandError> {
+        let generator = self.generator;
+        let names = args
             .iter()
-            .map(|p| p.parse().expect("Could not parse parameter to float."))
-            .collect()
-    });
-    match optype {
-        OpType::CX => {
-            let control = find_qubit(&com.args[0]);
-            let qubit = find_qubit(&com.args[1]);
-            controlled(generator, generator.qis_cnot_body(), control, qubit);
-        }
-        OpType::H => {
-            generator.emit_void_call(generator.qis_h_body(), &[find_qubit(&com.args[0]).into()]);
-        }
-        OpType::X => {
-            generator.emit_void_call(generator.qis_x_body(), &[find_qubit(&com.args[0]).into()]);
-        }
-        OpType::Y => {
-            generator.emit_void_call(generator.qis_y_body(), &[find_qubit(&com.args[0]).into()]);
-        }
-        OpType::Z => {
-            generator.emit_void_call(generator.qis_z_body(), &[find_qubit(&com.args[0]).into()]);
-        }
-        OpType::Measure => {
-            measure(
-                generator,
-                &qb_name(&com.args[0]),
-                &qb_name(&com.args[1]),
-                // &format!("results"),
-                // &format!("results{}", &com.args[1].1[0]),
-                qubits,
-                registers,
-            );
+            .map(|bit| self.bit_name(bit))
+            .collect::<Result<Vec<_>, _>>()?;
+        let one = get_one(generator);
+        let registers = &*self.registers;
+        let mut read = |i: usize| {
+            let result = registers
+                .get(&names[i])
+                .copied()
+                .flatten()
+                .unwrap_or_else(|| get_zero(generator));
+            equal(generator, result, one)
+        };
+        let writes = emit_classical(generator.context, &generator.builder, op, args, &mut read)?;
+        let zero = get_zero(generator);
+        for (i, value) in writes {
+            let result = generator
+                .builder
+                .build_select(value, one, zero, "result")
+                .into_pointer_value();
+            self.registers.insert(names[i].clone(), Some(result));
         }
-        // Instruction::Cz(inst) => {
-        //     let control = find_qubit(&inst.control);
-        //     let qubit = find_qubit(&inst.target);
-        //     controlled(
-        //         generator,
-        //         generator
-        //             .qis_cz_body()
-        //             .expect("qis_cz_body must be defined in the template"),
-        //         control,
-        //         qubit,
-        //     );
-        // }
-        // Instruction::Rx(inst) => {
-        //     generator.emit_void_call(
-        //         generator
-        //             .qis_rx_body()
-        //             .expect("qis_rx_body must be defined in the template"),
-        //         &[
-        //             generator.f64_to_f64(inst.theta),
-        //             find_qubit(&inst.qubit).into(),
-        //         ],
-        //     );
-        // }
-        // Instruction::Ry(inst) => {
-        //     generator.emit_void_call(
-        //         generator
-        //             .qis_ry_body()
-        //             .expect("qis_ry_body must be defined in the template"),
-        //         &[
-        //             generator.f64_to_f64(inst.theta),
-        //             find_qubit(&inst.qubit).into(),
-        //         ],
-        //     );
-        // }
-        OpType::Rz => {
-            generator.emit_void_call(
-                generator.qis_rz_body(),
-                &[
-                    generator.f64_to_f64(params.expect("Rz requires a parameter.")[0]),
-                    find_qubit(&com.args[0]).into(),
-                ],
-            );
+        Ok(())
+    }
+
+    /// The definition of a custom gate, see [`custom_gate`], with options
+    /// binding its symbols.
+    fn custom_gate(
+        &self,
+        op: &Operation,
+        args: &[Register],
+    ) -> Result<(QirOptions, Vec<Command>, Vec<String>), CommandError> {
+        let (bindings, commands, phases) = custom_gate(op, args, &self.options.bindings)?;
+        let options = QirOptions {
+            bindings,
+            ..self.options.clone()
+        };
+        Ok((options, commands, phases))
+    }
+
+    /// Emitter into the same function with other options. Box functions
+    /// depend on the bindings, so it keeps its own.
+    fn scoped<'b>(
+        &'b mut self,
+        options: &'b QirOptions,
+        box_functions: &'b mut HashMap<BoxID, (FunctionValue<'ctx>, f64)>,
+    ) -> Emitter<'b, 'ctx> {
+        Emitter {
+            generator: self.generator,
+            qubits: self.qubits,
+            registers: &mut *self.registers,
+            entry_point: self.entry_point,
+            options,
+            layout: self.layout,
+            box_functions,
+            phase: &mut *self.phase,
         }
-        // Instruction::S(inst) => {
-        //     generator.emit_void_call(
-        //         generator
-        //             .qis_s_body()
-        //             .expect("qis_s_body must be defined in the template"),
-        //         &[find_qubit(&inst.qubit).into()],
-        //     );
-        // }
-        // Instruction::SAdj(inst) => {
-        //     generator.emit_void_call(
-        //         generator
-        //             .qis_s_adj()
-        //             .expect("qis_s_adj must be defined in the template"),
-        //         &[find_qubit(&inst.qubit).into()],
-        //     );
-        // }
-        // Instruction::T(inst) => {
-        //     generator.emit_void_call(
-        //         generator
-        //             .qis_t_body()
-        //             .expect("qis_t_body must be defined in the template"),
-        //         &[find_qubit(&inst.qubit).into()],
-        //     );
-        // }
-        // Instruction::TAdj(inst) => {
-        //     generator.emit_void_call(
-        //         generator
-        //             .qis_t_adj()
-        //             .expect("qis_t_adj must be defined in the template"),
-        //         &[find_qubit(&inst.qubit).into()],
-        //     );
-        // }
-        OpType::Conditional => {
-            let (condition_bit, args) = match &com.args[..] {
-                [a, b @ ..] => (a, b),
-                _ => panic!("Not enough args to conditional."),
-            };
-            let mut conditional = com.op.conditional.as_ref().unwrap().clone();
-            // for now only support conditional circbox
-            if let Some(OpBox::CircBox { .. }) = conditional.op.op_box.as_ref() {
-                ()
+    }
+
+    /// Native gates of a gate or unitary box, see [`op_gates`].
+    fn op_gates(&self, op: &Operation, n_args: usize) -> Result<(Vec<Gate>, f64), CommandError> {
+        op_gates(op, n_args, &self.options.bindings)
+    }
+
+    /// Emit `op` on `args`, of which the first `n_controls` are controls in
+    /// addition to `controls`, conditioned on all controls being one. The
+    /// new controls are instead conditioned on `control_state`, big-endian,
+    /// by flipping those that should be zero around the op.
+    fn emit_controlled(
+        &mut self,
+        controls: &[BasicValueEnum<'ctx>],
+        n_controls: usize,
+        control_state: Option<u64>,
+        op: &Operation,
+        args: &[Register],
+    ) -> Result<(), CommandError> {
+        if args.len() < n_controls {
+            return Err(CommandError::Arity {
+                op_type: OpType::QControlBox,
+                expected: n_controls,
+                found: args.len(),
+            });
+        }
+        let (control_args, args) = args.split_at(n_controls);
+        let mut controls = controls.to_vec();
+        let mut flipped = vec![];
+        for (i, reg) in control_args.iter().enumerate() {
+            let control = self.find_qubit(reg)?;
+            let shift = (n_controls - 1 - i) as u32;
+            if let Some(state) = control_state {
+                if state.checked_shr(shift).unwrap_or(0) & 1 == 0 {
+                    flipped.push(control);
+                }
+            }
+            controls.push(control);
+        }
+        for &control in &flipped {
+            emit_gate(self.generator, &OpType::X, &[], &[control]);
+        }
+        self.emit_controlled_op(&controls, op, args)?;
+        for &control in &flipped {
+            emit_gate(self.generator, &OpType::X, &[], &[control]);
+        }
+        Ok(())
+    }
+
+    /// Emit `op` on `args`, conditioned on all `controls` being one.
+    fn emit_controlled_op(
+        &mut self,
+        controls: &[BasicValueEnum<'ctx>],
+        op: &Operation,
+        args: &[Register],
+    ) -> Result<(), CommandError> {
+        match (&op.op_type, op.op_box.as_ref()) {
+            (
+                OpType::QControlBox,
+                Some(OpBox::QControlBox {
+                    n_controls,
+                    op,
+                    control_state,
+                    ..
+                }),
+            ) => {
+                let state = control_state.as_ref().copied();
+                self.emit_controlled(controls, *n_controls as usize, state, op, args)
+            }
+            (OpType::CircBox, Some(OpBox::CircBox { circuit, .. })) => {
+                for inner in inline_circbox(circuit, args)? {
+                    self.emit_controlled(controls, 0, None, &inner.op, &inner.args)?;
+                }
+                let phase = self.phases(&inlined_phases(circuit))?;
+                self.emit_controlled_phase(controls, phase)
+            }
+            (OpType::CustomGate, _) => {
+                let (options, commands, phases) = self.custom_gate(op, args)?;
+                let mut box_functions = HashMap::new();
+                let mut emitter = self.scoped(&options, &mut box_functions);
+                for inner in &commands {
+                    emitter.emit_controlled(controls, 0, None, &inner.op, &inner.args)?;
+                }
+                let phase = emitter.phases(&phases)?;
+                emitter.emit_controlled_phase(controls, phase)
+            }
+            (OpType::QControlBox | OpType::CircBox, _) => {
+                Err(CommandError::MissingData(op.op_type.clone(), "box"))
+            }
+            _ => {
+                let (gates, phase) = self.op_gates(op, args.len())?;
+                let qubits = args
+                    .iter()
+                    .map(|reg| self.find_qubit(reg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                for gate in gates {
+                    let gate_args: Vec<_> = gate.args.iter().map(|&i| qubits[i]).collect();
+                    emit_controlled_gate(
+                        self.generator,
+                        controls,
+                        &gate.op_type,
+                        &gate.params,
+                        &gate_args,
+                    )?;
+                }
+                self.emit_controlled_phase(controls, phase)
+            }
+        }
+    }
+
+    /// Apply `phase` on the state where all `controls` are one. Under
+    /// controls the global phase of an operation becomes a relative one.
+    fn emit_controlled_phase(
+        &mut self,
+        controls: &[BasicValueEnum<'ctx>],
+        phase: f64,
+    ) -> Result<(), CommandError> {
+        *self.phase += emit_controlled_phase(self.generator, controls, phase)?;
+        Ok(())
+    }
+
+    /// Emit native gates acting on positions into `args`.
+    fn emit_gates(&self, gates: &[Gate], args: &[Register]) -> Result<(), CommandError> {
+        let qubits = args
+            .iter()
+            .map(|reg| self.find_qubit(reg))
+            .collect::<Result<Vec<_>, _>>()?;
+        for gate in gates {
+            let gate_args: Vec<_> = gate.args.iter().map(|&i| qubits[i]).collect();
+            emit_gate(self.generator, &gate.op_type, &gate.params, &gate_args);
+        }
+        Ok(())
+    }
+
+    /// Get or emit the function for a `CircBox` without bits, taking its
+    /// qubits in order, with the global phase each call applies.
+    fn box_function(
+        &mut self,
+        id: &BoxID,
+        circuit: &Circuit,
+    ) -> Result<(FunctionValue<'ctx>, f64), CommandError> {
+        if let Some(&entry) = self.box_functions.get(id) {
+            return Ok(entry);
+        }
+        let generator = self.generator;
+        let fn_type = generator.context.void_type().fn_type(
+            &vec![qubit_type(generator).into(); circuit.qubits.len()],
+            false,
+        );
+        // LLVM makes the name unique if several boxes share it
+        let name = circuit.name.as_ref().map_or("circbox", String::as_str);
+        let function = generator
+            .module
+            .add_function(name, fn_type, Some(Linkage::Internal));
+
+        let caller_block = generator.builder.get_insert_block();
+        let block = generator.context.append_basic_block(function, "entry");
+        generator.builder.position_at_end(block);
+
+        let layout = RegisterLayout::new(circuit);
+        let qubits = layout
+            .qubits
+            .names()
+            .iter()
+            .map(QirName::key)
+            .zip(function.get_param_iter())
+            .collect();
+        let mut registers = HashMap::new();
+        let mut phase = self.param(&circuit.phase)?;
+        let mut emitter = Emitter {
+            generator,
+            qubits: &qubits,
+            registers: &mut registers,
+            entry_point: function,
+            options: self.options,
+            layout: &layout,
+            box_functions: &mut *self.box_functions,
+            phase: &mut phase,
+        };
+        for com in &circuit.commands {
+            emitter.emit(com)?;
+        }
+        generator.builder.build_return(None);
+
+        if let Some(block) = caller_block {
+            generator.builder.position_at_end(block);
+        }
+        self.box_functions.insert(id.clone(), (function, phase));
+        Ok((function, phase))
+    }
+
+    /// Emit `commands` guarded by the values of `condition_bits`, where bit `i`
+    /// of the condition value is compared with `condition_bits[i]`.
+    fn emit_if(
+        &mut self,
+        conditional: &Conditional,
+        condition_bits: &[String],
+        commands: &[Command],
+    ) -> Result<(), CommandError> {
+        let generator = self.generator;
+
+        let value = conditional.value;
+        let mut condition: Option<IntValue> = None;
+        for (i, bit) in condition_bits.iter().enumerate() {
+            let comparison = if (value.checked_shr(i as u32).unwrap_or(0) & 1) == 1 {
+                get_one(generator)
             } else {
-                let newcirc = Circuit {
-                    name: None,
-                    phase: "".into(),
-                    commands: vec![Command {
-                        op: *conditional.op,
-                        args: args.to_vec(),
-                        opgroup: com.opgroup.clone(),
-                    }],
-                    qubits: args.to_vec(),
-                    // if inner op uses bits this will break
-                    bits: vec![],
-                    implicit_permutation: vec![],
-                };
-                conditional.op = Box::new(Operation {
-                    op_box: Some(OpBox::CircBox {
-                        circuit: newcirc,
-                        id: BoxID(uuid::Uuid::default()),
-                    }),
-                    op_type: OpType::CircBox,
-                    n_qb: None,
-                    params: None,
-                    signature: None,
-                    conditional: None,
+                get_zero(generator)
+            };
+            let result = self
+                .registers
+                .get(bit)
+                .copied()
+                .flatten()
+                .unwrap_or_else(|| get_zero(generator));
+            let bit_condition = equal(generator, result, comparison);
+            condition = Some(match condition {
+                Some(acc) => generator.builder.build_and(acc, bit_condition, "and"),
+                None => bit_condition,
+            });
+        }
+        let condition = condition.expect("width is checked to be positive");
+
+        let then_block = generator
+            .context
+            .append_basic_block(self.entry_point, "then");
+        let else_block = generator
+            .context
+            .append_basic_block(self.entry_point, "else");
+        generator
+            .builder
+            .build_conditional_branch(condition, then_block, else_block);
+
+        let continue_block = generator
+            .context
+            .append_basic_block(self.entry_point, "continue");
+
+        // The phase of the commands depends on the condition, so it is not
+        // global and is dropped
+        let phase = *self.phase;
+        let before = self.registers.clone();
+        generator.builder.position_at_end(then_block);
+        for inst in commands {
+            self.emit(inst)?;
+        }
+        let then_end = generator
+            .builder
+            .get_insert_block()
+            .expect("the builder is positioned");
+        generator.builder.build_unconditional_branch(continue_block);
+        *self.phase = phase;
+
+        // Results the commands set keep their previous value, `Zero` if
+        // unset, on the other branch and are merged by phis
+        let after = std::mem::replace(self.registers, before);
+        generator.builder.position_at_end(else_block);
+        let mut changed: Vec<_> = after
+            .into_iter()
+            .filter(|(bit, result)| self.registers.get(bit) != Some(result))
+            .collect();
+        changed.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let merges: Vec<_> = changed
+            .into_iter()
+            .filter_map(|(bit, result)| {
+                let previous = self.registers.get(&bit).copied().flatten();
+                Some((
+                    bit,
+                    result?,
+                    previous.unwrap_or_else(|| get_zero(generator)),
+                ))
+            })
+            .collect();
+        generator.builder.build_unconditional_branch(continue_block);
+
+        generator.builder.position_at_end(continue_block);
+        let result_type = opaque_pointer_type(generator, "Result");
+        for (bit, then_result, else_result) in merges {
+            let phi = generator.builder.build_phi(result_type, &bit);
+            phi.add_incoming(&[
+                (&then_result as &dyn BasicValue, then_end),
+                (&else_result, else_block),
+            ]);
+            let merged = phi.as_basic_value().into_pointer_value();
+            self.registers.insert(bit, Some(merged));
+        }
+        Ok(())
+    }
+}
+
+/// The condition of a `Conditional` command, its condition bits, with bit
+/// `i` of the condition value compared with the `i`-th, and the commands
+/// it guards.
+pub(crate) fn conditional_commands(
+    com: &Command,
+) -> Result<(&Conditional, &[Register], Vec<Command>), CommandError> {
+    let optype = &com.op.op_type;
+    let conditional = com
+        .op
+        .conditional
+        .as_ref()
+        .ok_or_else(|| CommandError::MissingData(optype.clone(), "condition"))?;
+    let (width, value) = (conditional.width, conditional.value);
+    if width == 0 || (width < 32 && value >> width != 0) {
+        return Err(CommandError::UnsupportedCondition { width, value });
+    }
+    if com.args.len() < width as usize {
+        return Err(CommandError::Arity {
+            op_type: optype.clone(),
+            expected: width as usize,
+            found: com.args.len(),
+        });
+    }
+    let (condition_bits, args) = com.args.split_at(width as usize);
+    let commands = match conditional.op.op_box.as_ref() {
+        Some(OpBox::CircBox { circuit, .. }) => inline_circbox(circuit, args)?,
+        _ => vec![Command {
+            op: (*conditional.op).clone(),
+            args: args.to_vec(),
+            opgroup: com.opgroup.clone(),
+            extra: com.extra.clone(),
+        }],
+    };
+    Ok((conditional, condition_bits, commands))
+}
+
+/// Evaluate a pytket parameter, in half-turns, with `bindings`.
+pub(crate) fn eval(param: &str, bindings: &Bindings) -> Result<f64, CommandError> {
+    eval_param(param, bindings).map_err(|err| match err {
+        ParamError::UnboundSymbol(symbol) => CommandError::UnboundSymbol(symbol),
+        _ => CommandError::BadParameter(param.to_string()),
+    })
+}
+
+fn params(op: &Operation, bindings: &Bindings) -> Result<Vec<f64>, CommandError> {
+    op.params
+        .as_ref()
+        .into_iter()
+        .flatten()
+        .map(|p| eval(p, bindings))
+        .collect()
+}
+
+/// Commands of the definition of a custom gate applied to `args`, `bindings`
+/// extended with the symbols of the definition bound to the gate's
+/// parameters, and the global phases of the definition, see
+/// [`inlined_phases`].
+pub(crate) fn custom_gate(
+    op: &Operation,
+    args: &[Register],
+    bindings: &Bindings,
+) -> Result<(Bindings, Vec<Command>, Vec<String>), CommandError> {
+    let (gate, params) = match op.op_box.as_ref() {
+        Some(OpBox::Composite { gate, params, .. }) => (gate, params),
+        _ => return Err(CommandError::MissingData(op.op_type.clone(), "box")),
+    };
+    let definition = &gate.definition;
+    check_op_arity(
+        &op.op_type,
+        args.len(),
+        definition.qubits.len() + definition.bits.len(),
+    )?;
+    if params.len() != gate.args.len() {
+        return Err(CommandError::ParamArity {
+            op_type: op.op_type.clone(),
+            expected: gate.args.len(),
+            found: params.len(),
+        });
+    }
+    // Parameters are evaluated with the caller's bindings, before any
+    // symbol of the definition shadows them
+    let values = params
+        .iter()
+        .map(|p| eval(p, bindings))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut bindings = bindings.clone();
+    bindings.extend(gate.args.iter().cloned().zip(values));
+    Ok((
+        bindings,
+        inline_circbox(definition, args)?,
+        inlined_phases(definition),
+    ))
+}
+
+/// Native gates of a gate or unitary box acting on `n_args` qubits, with
+/// the global phase, in half-turns, by which they differ from it. Symbols
+/// are evaluated with `bindings`.
+pub(crate) fn op_gates(
+    op: &Operation,
+    n_args: usize,
+    bindings: &Bindings,
+) -> Result<(Vec<Gate>, f64), CommandError> {
+    let optype = &op.op_type;
+    let missing_box = || CommandError::MissingData(optype.clone(), "box");
+    match optype {
+        OpType::Unitary1qBox => match op.op_box.as_ref() {
+            Some(OpBox::Unitary1qBox { matrix, .. }) => {
+                check_op_arity(optype, n_args, 1)?;
+                unitary1q(&matrix.0)
+            }
+            _ => Err(missing_box()),
+        },
+        OpType::Unitary2qBox => match op.op_box.as_ref() {
+            Some(OpBox::Unitary2qBox { matrix, .. }) => {
+                check_op_arity(optype, n_args, 2)?;
+                unitary2q(&matrix.0)
+            }
+            _ => Err(missing_box()),
+        },
+        OpType::ExpBox => match op.op_box.as_ref() {
+            Some(OpBox::ExpBox { matrix, t, .. }) => {
+                check_op_arity(optype, n_args, matrix.0.len().trailing_zeros() as usize)?;
+                exp_box(&matrix.0, *t)
+            }
+            _ => Err(missing_box()),
+        },
+        OpType::PauliExpBox => match op.op_box.as_ref() {
+            Some(OpBox::PauliExpBox { paulis, phase, .. }) => {
+                check_op_arity(optype, n_args, paulis.len())?;
+                Ok(pauli_exp(paulis, eval(phase, bindings)?))
+            }
+            _ => Err(missing_box()),
+        },
+        OpType::PhasePolyBox => match op.op_box.as_ref() {
+            Some(OpBox::PhasePolyBox {
+                n_qubits,
+                qubit_indices,
+                phase_polynomial,
+                linear_transformation,
+                ..
+            }) => {
+                let n_qubits = *n_qubits as usize;
+                check_op_arity(optype, n_args, n_qubits)?;
+                // The box's qubits, in pytket's order, are the arguments;
+                // map their index in the parities to their position
+                let mut units: Vec<_> = qubit_indices.iter().collect();
+                units.sort_by(|(a, _), (b, _)| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+                // The indices must be a permutation of the box's qubits
+                let invalid = || CommandError::NotUnitary(optype.clone());
+                if units.len() != n_qubits {
+                    return Err(invalid());
+                }
+                let mut positions = vec![None; n_qubits];
+                for (position, (_, index)) in units.into_iter().enumerate() {
+                    match positions.get_mut(*index as usize) {
+                        Some(slot @ None) => *slot = Some(position),
+                        _ => return Err(invalid()),
+                    }
+                }
+                let positions: Vec<usize> = positions.into_iter().flatten().collect();
+                let polynomial = phase_polynomial
+                    .iter()
+                    .map(|(parity, angle)| Ok((parity.clone(), eval(angle, bindings)?)))
+                    .collect::<Result<Vec<_>, CommandError>>()?;
+                let (mut gates, phase) = phase_poly(n_qubits, &polynomial, linear_transformation)?;
+                for gate in &mut gates {
+                    for arg in &mut gate.args {
+                        *arg = positions[*arg];
+                    }
+                }
+                Ok((gates, phase))
+            }
+            _ => Err(missing_box()),
+        },
+        OpType::Phase => {
+            check_op_arity(optype, n_args, 0)?;
+            match params(op, bindings)?[..] {
+                [phase] => Ok((vec![], phase)),
+                ref params => Err(CommandError::ParamArity {
+                    op_type: optype.clone(),
+                    expected: 1,
+                    found: params.len(),
+                }),
+            }
+        }
+        _ => {
+            let (n_qubits, n_params) = gate_signature(optype)
+                .ok_or_else(|| CommandError::UnsupportedOp(optype.clone()))?;
+            check_op_arity(optype, n_args, n_qubits)?;
+            let params = params(op, bindings)?;
+            if params.len() != n_params {
+                return Err(CommandError::ParamArity {
+                    op_type: optype.clone(),
+                    expected: n_params,
+                    found: params.len(),
                 });
             }
-            emit_if(
-                generator,
-                registers,
-                qubits,
-                entry_point,
-                &conditional,
-                &qb_name(condition_bit),
-            )
+            Ok(decompose(optype, &params).unwrap_or_else(|| {
+                (
+                    vec![Gate::new(optype.clone(), params, (0..n_qubits).collect())],
+                    0.0,
+                )
+            }))
         }
-        _ => panic!("unsupported optype"),
     }
 }
 
-fn emit_if<'ctx>(
+/// Emit a gate with a native QIS function, see [`crate::decompose::is_native`].
+/// Angles are given in half-turns and emitted in radians.
+fn emit_gate<'ctx>(
     generator: &CodeGenerator<'ctx>,
-    registers: &mut HashMap<String, Option<PointerValue<'ctx>>>,
-    qubits: &HashMap<String, BasicValueEnum<'ctx>>,
-    entry_point: FunctionValue,
-    conditional: &Conditional,
-    condition_bit: &String,
+    op_type: &OpType,
+    params: &[f64],
+    qubits: &[BasicValueEnum<'ctx>],
 ) {
-    let inner_circ = if let Some(OpBox::CircBox { circuit, .. }) = conditional.op.op_box.as_ref() {
-        circuit
-    } else {
-        panic!("only works with CircBoxes.")
+    let single = |function| {
+        generator.emit_void_call(function, &[qubits[0].into()]);
     };
-
-    let comparison = match (conditional.width, conditional.value) {
-        (1, 1) => get_one(generator),
-        (1, 0) => get_zero(generator),
-        _ => panic!("only supports condtioning on one bit"),
+    let rotation = |function| {
+        let angle = generator.f64_to_f64(half_turns_to_radians(params[0]));
+        generator.emit_void_call(function, &[angle, qubits[0].into()]);
     };
-    let result = registers
-        .get(condition_bit)
-        .unwrap_or_else(|| panic!("Result {} not found.", condition_bit))
-        .unwrap_or_else(|| get_zero(generator));
-
-    let condition = equal(generator, result, comparison);
-    let then_block = generator.context.append_basic_block(entry_point, "then");
-    let else_block = generator.context.append_basic_block(entry_point, "else");
-    generator
-        .builder
-        .build_conditional_branch(condition, then_block, else_block);
+    match op_type {
+        OpType::H => single(generator.qis_h_body()),
+        OpType::X => single(generator.qis_x_body()),
+        OpType::Y => single(generator.qis_y_body()),
+        OpType::Z => single(generator.qis_z_body()),
+        OpType::S => single(generator.qis_s_body()),
+        OpType::Sdg => single(generator.qis_s_adj()),
+        OpType::T => single(generator.qis_t_body()),
+        OpType::Tdg => single(generator.qis_t_adj()),
+        OpType::Reset => single(qis_function(generator, "reset__body", 1)),
+        OpType::Rx => rotation(generator.qis_rx_body()),
+        OpType::Ry => rotation(generator.qis_ry_body()),
+        OpType::Rz => rotation(generator.qis_rz_body()),
+        OpType::CX => {
+            controlled(generator, generator.qis_cnot_body(), qubits[0], qubits[1]);
+        }
+        OpType::CZ => {
+            controlled(generator, generator.qis_cz_body(), qubits[0], qubits[1]);
+        }
+        OpType::SWAP | OpType::CCX => {
+            let function = match op_type {
+                OpType::SWAP => qis_function(generator, "swap__body", 2),
+                _ => qis_function(generator, "ccx__body", 3),
+            };
+            let args: Vec<BasicMetadataValueEnum> = qubits.iter().map(|&qb| qb.into()).collect();
+            generator.emit_void_call(function, &args);
+        }
+        _ => unreachable!("{:?} has no QIS function", op_type),
+    }
+}
 
-    let continue_block = generator
-        .context
-        .append_basic_block(entry_point, "continue");
+/// Emit a native gate controlled on all of `controls`, with the controlled
+/// QIS functions where they exist and otherwise in terms of multi-controlled X.
+fn emit_controlled_gate<'ctx>(
+    generator: &CodeGenerator<'ctx>,
+    controls: &[BasicValueEnum<'ctx>],
+    op_type: &OpType,
+    params: &[f64],
+    qubits: &[BasicValueEnum<'ctx>],
+) -> Result<(), CommandError> {
+    if controls.is_empty() {
+        emit_gate(generator, op_type, params, qubits);
+        return Ok(());
+    }
+    let with = |extra: &[BasicValueEnum<'ctx>]| -> Vec<_> {
+        controls.iter().chain(extra).copied().collect()
+    };
+    let target = qubits[qubits.len() - 1];
+    match op_type {
+        OpType::H => emit_ctl_call(generator, "h__ctl", controls, target),
+        OpType::X => emit_ctl_call(generator, "x__ctl", controls, target),
+        OpType::Y => emit_ctl_call(generator, "y__ctl", controls, target),
+        OpType::Z => emit_ctl_call(generator, "z__ctl", controls, target),
+        OpType::S => emit_ctl_call(generator, "s__ctl", controls, target),
+        OpType::Sdg => emit_ctl_call(generator, "s__ctladj", controls, target),
+        OpType::T => emit_ctl_call(generator, "t__ctl", controls, target),
+        OpType::Tdg => emit_ctl_call(generator, "t__ctladj", controls, target),
+        OpType::CX => emit_ctl_call(generator, "x__ctl", &with(&qubits[..1]), target),
+        OpType::CZ => emit_ctl_call(generator, "z__ctl", &with(&qubits[..1]), target),
+        OpType::CCX => emit_ctl_call(generator, "x__ctl", &with(&qubits[..2]), target),
+        // Only the middle CX of SWAP = CX(1, 0) CX(0, 1) CX(1, 0) needs controls
+        OpType::SWAP => {
+            let reversed = [qubits[1], qubits[0]];
+            emit_gate(generator, &OpType::CX, &[], &reversed);
+            emit_ctl_call(generator, "x__ctl", &with(&qubits[..1]), target);
+            emit_gate(generator, &OpType::CX, &[], &reversed);
+        }
+        // X Rz(-θ/2) X Rz(θ/2) = Rz(θ), and likewise for Ry
+        OpType::Rz | OpType::Ry => {
+            let half = params[0] / 2.0;
+            emit_gate(generator, op_type, &[half], qubits);
+            emit_ctl_call(generator, "x__ctl", controls, target);
+            emit_gate(generator, op_type, &[-half], qubits);
+            emit_ctl_call(generator, "x__ctl", controls, target);
+        }
+        OpType::Rx => {
+            emit_gate(generator, &OpType::H, &[], qubits);
+            emit_controlled_gate(generator, controls, &OpType::Rz, params, qubits)?;
+            emit_gate(generator, &OpType::H, &[], qubits);
+        }
+        _ => return Err(CommandError::UnsupportedOp(op_type.clone())),
+    }
+    Ok(())
+}
 
-    let mut emit_block = |block, insts| {
-        generator.builder.position_at_end(block);
-        for inst in insts {
-            emit(generator, inst, qubits, registers, entry_point);
+/// Emit the phase `e^{iπ phase}` on the state where all `controls` are one.
+/// On `n` controls it is an Rz on the last one controlled on the others, and
+/// half the phase on the others. Returns the phase left on no controls, which
+/// is global.
+fn emit_controlled_phase<'ctx>(
+    generator: &CodeGenerator<'ctx>,
+    controls: &[BasicValueEnum<'ctx>],
+    phase: f64,
+) -> Result<f64, CommandError> {
+    match controls.split_last() {
+        Some(_) if phase == 0.0 => Ok(0.0),
+        Some((&last, others)) => {
+            emit_controlled_gate(generator, others, &OpType::Rz, &[phase], &[last])?;
+            emit_controlled_phase(generator, others, phase / 2.0)
         }
+        None => Ok(phase),
+    }
+}
 
-        generator.builder.build_unconditional_branch(continue_block);
-    };
+/// Call `__quantum__qis__<name>` on an array of `controls` and `target`. The
+/// array is released after the call.
+fn emit_ctl_call<'ctx>(
+    generator: &CodeGenerator<'ctx>,
+    name: &str,
+    controls: &[BasicValueEnum<'ctx>],
+    target: BasicValueEnum<'ctx>,
+) {
+    let context = generator.context;
+    let array_type = opaque_pointer_type(generator, "Array");
+    let (i32_type, i64_type) = (context.i32_type(), context.i64_type());
 
-    emit_block(then_block, &inner_circ.commands);
-    emit_block(else_block, &vec![]);
-    generator.builder.position_at_end(continue_block);
+    let create = rt_function(
+        generator,
+        "array_create_1d",
+        array_type.fn_type(&[i32_type.into(), i64_type.into()], false),
+    );
+    let get_element = rt_function(
+        generator,
+        "array_get_element_ptr_1d",
+        context
+            .i8_type()
+            .ptr_type(AddressSpace::Generic)
+            .fn_type(&[array_type.into(), i64_type.into()], false),
+    );
+    let update_reference_count = rt_function(
+        generator,
+        "array_update_reference_count",
+        context
+            .void_type()
+            .fn_type(&[array_type.into(), i32_type.into()], false),
+    );
+    let function = generator
+        .module
+        .get_function(&format!("__quantum__qis__{}", name))
+        .unwrap_or_else(|| {
+            let fn_type = context
+                .void_type()
+                .fn_type(&[array_type.into(), qubit_type(generator).into()], false);
+            generator
+                .module
+                .add_function(&format!("__quantum__qis__{}", name), fn_type, None)
+        });
+
+    // Arrays of qubits hold 8 byte pointers
+    let array = generator
+        .emit_call_with_return(
+            create,
+            &[
+                i32_type.const_int(8, false).into(),
+                i64_type.const_int(controls.len() as u64, false).into(),
+            ],
+            "controls",
+        )
+        .into_pointer_value();
+    for (i, &control) in controls.iter().enumerate() {
+        let element = generator
+            .emit_call_with_return(
+                get_element,
+                &[array.into(), i64_type.const_int(i as u64, false).into()],
+                "element",
+            )
+            .into_pointer_value();
+        let element = generator
+            .builder
+            .build_bitcast(
+                element,
+                qubit_type(generator).ptr_type(AddressSpace::Generic),
+                "control",
+            )
+            .into_pointer_value();
+        generator.builder.build_store(element, control);
+    }
+    generator.emit_void_call(function, &[array.into(), target.into()]);
+    generator.emit_void_call(
+        update_reference_count,
+        &[array.into(), i32_type.const_int(-1_i64 as u64, true).into()],
+    );
+}
+
+/// Get or declare `__quantum__rt__<name>` with type `fn_type`.
+fn rt_function<'ctx>(
+    generator: &CodeGenerator<'ctx>,
+    name: &str,
+    fn_type: FunctionType<'ctx>,
+) -> FunctionValue<'ctx> {
+    let name = format!("__quantum__rt__{}", name);
+    generator
+        .module
+        .get_function(&name)
+        .unwrap_or_else(|| generator.module.add_function(&name, fn_type, None))
+}
+
+/// Get or declare `__quantum__qis__<name>`, taking `n_qubits` qubits, for
+/// QIS functions qirlib does not provide.
+fn qis_function<'ctx>(
+    generator: &CodeGenerator<'ctx>,
+    name: &str,
+    n_qubits: usize,
+) -> FunctionValue<'ctx> {
+    let name = format!("__quantum__qis__{}", name);
+    generator.module.get_function(&name).unwrap_or_else(|| {
+        let fn_type = generator
+            .context
+            .void_type()
+            .fn_type(&vec![qubit_type(generator).into(); n_qubits], false);
+        generator.module.add_function(&name, fn_type, None)
+    })
+}
+
+/// The `%Qubit*` type.
+fn qubit_type<'ctx>(generator: &CodeGenerator<'ctx>) -> PointerType<'ctx> {
+    opaque_pointer_type(generator, "Qubit")
+}
+
+/// Pointer to the opaque struct `name`, declared if the module lacks it.
+fn opaque_pointer_type<'ctx>(generator: &CodeGenerator<'ctx>, name: &str) -> PointerType<'ctx> {
+    generator
+        .module
+        .get_struct_type(name)
+        .unwrap_or_else(|| generator.context.opaque_struct_type(name))
+        .ptr_type(AddressSpace::Generic)
 }
 
 fn get_zero<'a>(generator: &CodeGenerator<'a>) -> PointerValue<'a> {
@@ -315,3 +1252,595 @@ pub(crate) fn equal<'a>(
         .emit_call_with_return(generator.rt_result_equal(), &[result1, result2], "equal")
         .into_int_value()
 }
+
+/// Convert textual QIR, as returned by `qirlib::generation::emit::ir`, to bitcode.
+pub fn ir_to_bitcode(ir: &str) -> Result<Vec<u8>, String> {
+    let context = Context::create();
+    let buffer = MemoryBuffer::create_from_memory_range_copy(ir.as_bytes(), "tket_circuit");
+    let module = context
+        .create_module_from_ir(buffer)
+        .map_err(|err| err.to_string())?;
+    Ok(module.write_bitcode_to_memory().as_slice().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_to_qir() {
+        let ir = json_to_qir(
+            r#"{"bits": [["c", [0]], ["c", [1]]], "commands": [{"args": [["q", [0]]], "op": {"type": "H"}}, {"args": [["q", [0]], ["q", [1]]], "op": {"type": "CX"}}, {"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}, {"args": [["q", [1]]], "op": {"params": ["0.2"], "type": "Rz"}}, {"args": [["q", [1]], ["c", [1]]], "op": {"type": "Measure"}}], "implicit_permutation": [[["q", [0]], ["q", [0]]], [["q", [1]], ["q", [1]]]], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .expect("Conversion failed.");
+        assert!(ir.contains("rz"));
+    }
+
+    #[test]
+    fn test_json_to_qir_conditional() {
+        json_to_qir(
+            r#"{"bits": [["c", [0]], ["c", [1]]], "commands": [{"args": [["q", [0]]], "op": {"type": "X"}}, {"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}, {"args": [["c", [0]], ["q", [1]]], "op": {"conditional": {"op": {"type": "Z"}, "value": 0, "width": 1}, "type": "Conditional"}}, {"args": [["c", [1]], ["q", [0]], ["q", [1]]], "op": {"conditional": {"op": {"box": {"circuit": {"bits": [], "commands": [{"args": [["q", [0]], ["q", [1]]], "op": {"type": "CX"}}], "implicit_permutation": [[["q", [0]], ["q", [0]]], [["q", [1]], ["q", [1]]]], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}, "type": "CircBox"}, "value": 1, "width": 1}, "type": "Conditional"}}], "implicit_permutation": [[["q", [0]], ["q", [0]]], [["q", [1]], ["q", [1]]]], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .expect("Conversion failed.");
+    }
+
+    #[test]
+    fn test_multi_bit_conditions() {
+        let circ = |value: u32| {
+            format!(
+                r#"{{"bits": [["c", [0]], ["c", [1]], ["c", [2]]], "commands": [{{"args": [["c", [0]], ["c", [1]], ["c", [2]], ["q", [0]]], "op": {{"conditional": {{"op": {{"type": "X"}}, "value": {}, "width": 3}}, "type": "Conditional"}}}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}}"#,
+                value
+            )
+        };
+        let ir = json_to_qir(&circ(5)).expect("Conversion failed.");
+        assert_eq!(
+            ir.matches("call i1 @__quantum__rt__result_equal(").count(),
+            3
+        );
+        assert!(ir.contains(" = and i1 "));
+
+        let err = json_to_qir(&circ(9)).unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::UnsupportedCondition { width: 3, value: 9 },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_classical_ops() {
+        let ir = json_to_qir(
+            r#"{"bits": [["c", [0]], ["c", [1]], ["c", [2]], ["c", [3]]], "commands": [{"args": [["c", [0]], ["c", [1]]], "op": {"classical": {"n_i": 0, "n_io": 0, "n_o": 2, "name": "SetBits", "values": [true, false]}, "type": "SetBits"}}, {"args": [["c", [0]], ["c", [2]]], "op": {"conditional": {"op": {"classical": {"n_i": 0, "n_io": 0, "n_o": 1, "name": "SetBits", "values": [true]}, "type": "SetBits"}, "value": 1, "width": 1}, "type": "Conditional"}}, {"args": [["c", [0]], ["c", [1]], ["c", [2]], ["c", [1]], ["c", [2]], ["c", [3]]], "op": {"classical": {"n": 2, "op": {"classical": {"n_i": 2, "n_io": 0, "n_o": 1, "name": "AND", "values": [false, false, false, true]}, "type": "ExplicitPredicate"}}, "type": "MultiBit"}}, {"args": [["c", [3]], ["c", [0]]], "op": {"classical": {"n_i": 1, "n_io": 0, "n_o": 1, "name": "CopyBits"}, "type": "CopyBits"}}], "implicit_permutation": [], "phase": "0.0", "qubits": []}"#,
+        )
+        .expect("Conversion failed.");
+        assert!(ir.contains("select i1"));
+        assert!(ir.contains("phi %Result*"));
+        assert!(ir.contains("icmp eq i64"));
+
+        let err = json_to_qir(
+            r#"{"bits": [["c", [0]]], "commands": [{"args": [["c", [0]]], "op": {"classical": {"n_i": 0, "n_io": 0, "n_o": 2, "name": "SetBits", "values": [true, false]}, "type": "SetBits"}}], "implicit_permutation": [], "phase": "0.0", "qubits": []}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::Arity {
+                    expected: 2,
+                    found: 1,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_circbox() {
+        json_to_qir(
+            r#"{"bits": [["c", [0]]], "commands": [{"args": [["q", [1]], ["q", [0]], ["c", [0]]], "op": {"box": {"circuit": {"bits": [["m", [0]]], "commands": [{"args": [["a", [0]], ["a", [1]]], "op": {"type": "CZ"}}, {"args": [["a", [1]], ["m", [0]]], "op": {"type": "Measure"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["a", [0]], ["a", [1]]]}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}, "type": "CircBox"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .expect("Conversion failed.");
+
+        let err = json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"box": {"circuit": {"bits": [], "commands": [], "implicit_permutation": [], "phase": "0.0", "qubits": [["a", [0]], ["a", [1]]]}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}, "type": "CircBox"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::Arity {
+                    expected: 2,
+                    found: 1,
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_box_functions() {
+        let circ: Circuit = serde_json::from_str(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]], ["q", [1]]], "op": {"box": {"circuit": {"bits": [], "commands": [{"args": [["a", [0]]], "op": {"type": "H"}}, {"args": [["a", [0]], ["a", [1]]], "op": {"type": "CZ"}}], "implicit_permutation": [], "name": "oracle", "phase": "0.0", "qubits": [["a", [0]], ["a", [1]]]}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}, "type": "CircBox"}}, {"args": [["q", [1]], ["q", [0]]], "op": {"box": {"circuit": {"bits": [], "commands": [{"args": [["a", [0]]], "op": {"type": "H"}}, {"args": [["a", [0]], ["a", [1]]], "op": {"type": "CZ"}}], "implicit_permutation": [], "name": "oracle", "phase": "0.0", "qubits": [["a", [0]], ["a", [1]]]}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}, "type": "CircBox"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .unwrap();
+        let options = QirOptions {
+            box_functions: true,
+            ..QirOptions::default()
+        };
+        let ir = circuit_to_qir_with(&circ, &options).expect("Conversion failed.");
+        assert_eq!(ir.matches("define internal void @oracle(").count(), 1);
+        assert_eq!(ir.matches("call void @oracle(").count(), 2);
+
+        let ir = circuit_to_qir(&circ).expect("Conversion failed.");
+        assert!(!ir.contains("@oracle("));
+    }
+
+    #[test]
+    fn test_single_qubit_gates() {
+        let ir = json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"type": "SX"}}, {"args": [["q", [0]]], "op": {"type": "Tdg"}}, {"args": [["q", [0]]], "op": {"params": ["0.1", "0.2", "0.3"], "type": "U3"}}, {"args": [["q", [0]]], "op": {"params": ["0.1", "0.2", "0.3"], "type": "TK1"}}, {"args": [["q", [0]]], "op": {"type": "Reset"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        )
+        .expect("Conversion failed.");
+        assert!(ir.contains("__quantum__qis__rx__body"));
+        assert!(ir.contains("__quantum__qis__ry__body"));
+        assert!(ir.contains("__quantum__qis__t__adj"));
+        assert!(ir.contains("__quantum__qis__reset__body"));
+
+        let err = json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"params": ["0.1"], "type": "U3"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::ParamArity { expected: 3, .. },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_unitary1q_box() {
+        let circ = |matrix: &str| {
+            format!(
+                r#"{{"bits": [], "commands": [{{"args": [["q", [0]]], "op": {{"box": {{"id": "91810268-1b06-47b4-8609-992d066b56f2", "matrix": {}, "type": "Unitary1qBox"}}, "type": "Unitary1qBox"}}}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}}"#,
+                matrix
+            )
+        };
+        json_to_qir(&circ(
+            "[[[0.0, 0.0], [1.0, 0.0]], [[1.0, 0.0], [0.0, 0.0]]]",
+        ))
+        .expect("Conversion failed.");
+
+        let err = json_to_qir(&circ(
+            "[[[1.0, 0.0], [1.0, 0.0]], [[0.0, 0.0], [1.0, 0.0]]]",
+        ))
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::NotUnitary(OpType::Unitary1qBox),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_pauli_exp_box() {
+        let circ: Circuit = serde_json::from_str(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]], ["q", [1]], ["q", [2]]], "op": {"box": {"id": "91810268-1b06-47b4-8609-992d066b56f2", "paulis": ["X", "I", "Y"], "phase": "0.5*theta", "type": "PauliExpBox"}, "type": "PauliExpBox"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]], ["q", [2]]]}"#,
+        )
+        .unwrap();
+        let err = circuit_to_qir(&circ).unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::UnboundSymbol(_),
+                ..
+            }
+        ));
+        let options = QirOptions {
+            bindings: Bindings::from([("theta".to_string(), 0.3)]),
+            ..QirOptions::default()
+        };
+        circuit_to_qir_with(&circ, &options).expect("Conversion failed.");
+    }
+
+    #[test]
+    fn test_phase_poly_box() {
+        let circ: Circuit = serde_json::from_str(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]], ["q", [1]]], "op": {"box": {"id": "91810268-1b06-47b4-8609-992d066b56f2", "linear_transformation": [[true, false], [true, true]], "n_qubits": 2, "phase_polynomial": [[[true, true], "0.25"], [[false, true], "a"]], "qubit_indices": [[["q", [0]], 1], [["q", [1]], 0]], "type": "PhasePolyBox"}, "type": "PhasePolyBox"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            circuit_to_qir(&circ),
+            Err(TketQirError::Command {
+                source: CommandError::UnboundSymbol(_),
+                ..
+            })
+        ));
+        let options = QirOptions {
+            bindings: Bindings::from([("a".to_string(), 0.5)]),
+            ..QirOptions::default()
+        };
+        circuit_to_qir_with(&circ, &options).expect("Conversion failed.");
+
+        for qubit_indices in [
+            r#"[[["q", [0]], 0], [["q", [1]], 0]]"#,
+            r#"[[["q", [0]], 0], [["q", [1]], 2]]"#,
+            r#"[[["q", [0]], 0]]"#,
+        ] {
+            let circ: Circuit = serde_json::from_str(&format!(
+                r#"{{"bits": [], "commands": [{{"args": [["q", [0]], ["q", [1]]], "op": {{"box": {{"id": "91810268-1b06-47b4-8609-992d066b56f2", "linear_transformation": [[true, false], [false, true]], "n_qubits": 2, "phase_polynomial": [[[true, true], "0.25"]], "qubit_indices": {}, "type": "PhasePolyBox"}}, "type": "PhasePolyBox"}}}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}}"#,
+                qubit_indices
+            ))
+            .unwrap();
+            assert!(
+                matches!(
+                    circuit_to_qir(&circ),
+                    Err(TketQirError::Command {
+                        source: CommandError::NotUnitary(OpType::PhasePolyBox),
+                        ..
+                    })
+                ),
+                "{}",
+                qubit_indices
+            );
+        }
+    }
+
+    #[test]
+    fn test_unitary2q_box() {
+        // iSWAP
+        json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [1]], ["q", [0]]], "op": {"box": {"id": "91810268-1b06-47b4-8609-992d066b56f2", "matrix": [[[1.0, 0.0], [0.0, 0.0], [0.0, 0.0], [0.0, 0.0]], [[0.0, 0.0], [0.0, 0.0], [0.0, 1.0], [0.0, 0.0]], [[0.0, 0.0], [0.0, 1.0], [0.0, 0.0], [0.0, 0.0]], [[0.0, 0.0], [0.0, 0.0], [0.0, 0.0], [1.0, 0.0]]], "type": "Unitary2qBox"}, "type": "Unitary2qBox"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .expect("Conversion failed.");
+    }
+
+    #[test]
+    fn test_exp_box() {
+        // e^{itXX}
+        json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]], ["q", [1]]], "op": {"box": {"id": "91810268-1b06-47b4-8609-992d066b56f2", "matrix": [[[0.0, 0.0], [0.0, 0.0], [0.0, 0.0], [1.0, 0.0]], [[0.0, 0.0], [0.0, 0.0], [1.0, 0.0], [0.0, 0.0]], [[0.0, 0.0], [1.0, 0.0], [0.0, 0.0], [0.0, 0.0]], [[1.0, 0.0], [0.0, 0.0], [0.0, 0.0], [0.0, 0.0]]], "phase": 0.3, "type": "ExpBox"}, "type": "ExpBox"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .expect("Conversion failed.");
+    }
+
+    #[test]
+    fn test_qcontrol_box() {
+        let qcontrol = |n_controls: u32, op: &str| {
+            format!(
+                r#"{{"box": {{"id": "91810268-1b06-47b4-8609-992d066b56f2", "n_controls": {}, "op": {}, "type": "QControlBox"}}, "type": "QControlBox"}}"#,
+                n_controls, op
+            )
+        };
+        let circuit = |op: &str| {
+            format!(
+                r#"{{"bits": [["c", [0]]], "commands": [{{"args": [["q", [0]], ["q", [1]], ["q", [2]]], "op": {}}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]], ["q", [2]]]}}"#,
+                op
+            )
+        };
+
+        // CCZ
+        let ir =
+            json_to_qir(&circuit(&qcontrol(2, r#"{"type": "Z"}"#))).expect("Conversion failed.");
+        assert!(ir.contains("call void @__quantum__qis__z__ctl(%Array*"));
+        assert!(ir.contains("@__quantum__rt__array_create_1d(i32 8, i64 2)"));
+        assert!(!ir.contains("@__quantum__qis__x__body"));
+
+        // Controlled on q[0] being zero and q[1] one, so q[0] is flipped
+        // around the controlled Z
+        let zero_one = r#"{"box": {"control_state": 1, "id": "91810268-1b06-47b4-8609-992d066b56f2", "n_controls": 2, "op": {"type": "Z"}, "type": "QControlBox"}, "type": "QControlBox"}"#;
+        let ir = json_to_qir(&circuit(zero_one)).expect("Conversion failed.");
+        let flips: Vec<_> = ir
+            .match_indices("call void @__quantum__qis__x__body(%Qubit* ")
+            .map(|(i, _)| i)
+            .collect();
+        let controlled = ir
+            .find("call void @__quantum__qis__z__ctl(")
+            .expect("No controlled Z.");
+        assert_eq!(flips.len(), 2);
+        assert!(flips[0] < controlled && controlled < flips[1]);
+        let flipped_qubit = |i: usize| ir[i..].lines().next().unwrap().to_string();
+        assert_eq!(flipped_qubit(flips[0]), flipped_qubit(flips[1]));
+        let all_ones = zero_one.replace(r#""control_state": 1"#, r#""control_state": 3"#);
+        let ir = json_to_qir(&circuit(&all_ones)).expect("Conversion failed.");
+        assert!(!ir.contains("@__quantum__qis__x__body"));
+
+        // Nested boxes, with a gate without a controlled QIS function and a
+        // global phase which becomes relative
+        let nested = qcontrol(1, &qcontrol(1, r#"{"params": ["0.3"], "type": "U1"}"#));
+        let ir = json_to_qir(&circuit(&nested)).expect("Conversion failed.");
+        assert!(ir.contains("@__quantum__rt__array_create_1d(i32 8, i64 2)"));
+        assert!(ir.contains("call void @__quantum__qis__x__ctl(%Array*"));
+
+        let inner_circuit = r#"{"bits": [["c", [0]]], "commands": [{"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#;
+        let measure_box = format!(
+            r#"{{"box": {{"circuit": {}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}}, "type": "CircBox"}}"#,
+            inner_circuit
+        );
+        let circ = format!(
+            r#"{{"bits": [["c", [0]]], "commands": [{{"args": [["q", [0]], ["q", [1]], ["c", [0]]], "op": {}}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}}"#,
+            qcontrol(1, &measure_box)
+        );
+        assert!(matches!(
+            json_to_qir(&circ),
+            Err(TketQirError::Command {
+                source: CommandError::UnsupportedOp(OpType::Measure),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_custom_gate() {
+        let definition = r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"params": ["a"], "type": "Rx"}}, {"args": [["q", [0]], ["q", [1]]], "op": {"type": "CX"}}, {"args": [["q", [1]]], "op": {"params": ["2*a + b"], "type": "Rz"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#;
+        let custom_gate = |params: &str| {
+            format!(
+                r#"{{"box": {{"gate": {{"args": ["a", "b"], "definition": {}, "name": "mygate"}}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "params": {}, "type": "CustomGate"}}, "type": "CustomGate"}}"#,
+                definition, params
+            )
+        };
+        let circuit = |op: &str| {
+            format!(
+                r#"{{"bits": [], "commands": [{{"args": [["q", [1]], ["q", [0]]], "op": {}}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}}"#,
+                op
+            )
+        };
+
+        let circ: Circuit =
+            serde_json::from_str(&circuit(&custom_gate(r#"["0.5*a", "0.25"]"#))).unwrap();
+        assert!(matches!(
+            circuit_to_qir(&circ),
+            Err(TketQirError::Command {
+                source: CommandError::UnboundSymbol(_),
+                ..
+            })
+        ));
+        // The caller's `a` is distinct from the definition's
+        let options = QirOptions {
+            bindings: Bindings::from([("a".to_string(), 0.5)]),
+            ..QirOptions::default()
+        };
+        let ir = circuit_to_qir_with(&circ, &options).expect("Conversion failed.");
+        assert!(ir.contains("rx"));
+        assert!(ir.contains("rz"));
+
+        assert!(matches!(
+            json_to_qir(&circuit(&custom_gate(r#"["0.5"]"#))),
+            Err(TketQirError::Command {
+                source: CommandError::ParamArity {
+                    expected: 2,
+                    found: 1,
+                    ..
+                },
+                ..
+            })
+        ));
+
+        let qcontrol = format!(
+            r#"{{"box": {{"id": "91810268-1b06-47b4-8609-992d066b56f2", "n_controls": 1, "op": {}, "type": "QControlBox"}}, "type": "QControlBox"}}"#,
+            custom_gate(r#"["0.5", "0.25"]"#)
+        );
+        let circ = format!(
+            r#"{{"bits": [], "commands": [{{"args": [["q", [2]], ["q", [1]], ["q", [0]]], "op": {}}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]], ["q", [2]]]}}"#,
+            qcontrol
+        );
+        let ir = json_to_qir(&circ).expect("Conversion failed.");
+        assert!(ir.contains("call void @__quantum__qis__x__ctl(%Array*"));
+    }
+
+    #[test]
+    fn test_implicit_permutation() {
+        let circuit = |permutation: &str| {
+            format!(
+                r#"{{"bits": [["c", [0]]], "commands": [{{"args": [["q", [0]]], "op": {{"type": "X"}}}}, {{"args": [["q", [0]], ["c", [0]]], "op": {{"type": "Measure"}}}}], "implicit_permutation": {}, "phase": "0.0", "qubits": [["q", [0]], ["q", [1]], ["q", [2]]]}}"#,
+                permutation
+            )
+        };
+        let cycle = circuit(
+            r#"[[["q", [0]], ["q", [1]]], [["q", [1]], ["q", [2]]], [["q", [2]], ["q", [0]]]]"#,
+        );
+        let circ: Circuit = serde_json::from_str(&cycle).unwrap();
+
+        let swapped = circuit_to_qir(&circ).expect("Conversion failed.");
+        let with_mode = |permutation| {
+            circuit_to_qir_with(
+                &circ,
+                &QirOptions {
+                    permutation: Some(permutation),
+                    ..QirOptions::default()
+                },
+            )
+        };
+        assert_eq!(with_mode(PermutationMode::Swaps).unwrap(), swapped);
+        // Relabelling runtime qubits would move no state
+        assert!(matches!(
+            with_mode(PermutationMode::Relabel),
+            Err(TketQirError::Relabel)
+        ));
+        // The state of each qubit, as named by its SSA value, after the SWAPs
+        let mut state: HashMap<String, String> = ["q0", "q1", "q2"]
+            .iter()
+            .map(|name| (name.to_string(), name.to_string()))
+            .collect();
+        for line in swapped.lines() {
+            if let Some(args) = line
+                .trim()
+                .strip_prefix("call void @__quantum__qis__swap__body(%Qubit* %")
+            {
+                let (a, b) = args
+                    .trim_end_matches(')')
+                    .split_once(", %Qubit* %")
+                    .expect("Unexpected SWAP.");
+                let (state_a, state_b) = (state[a].clone(), state[b].clone());
+                state.insert(a.to_string(), state_b);
+                state.insert(b.to_string(), state_a);
+            }
+        }
+        assert_eq!(state["q1"], "q0");
+        assert_eq!(state["q2"], "q1");
+        assert_eq!(state["q0"], "q2");
+
+        let identity = circuit(
+            r#"[[["q", [0]], ["q", [0]]], [["q", [1]], ["q", [1]]], [["q", [2]], ["q", [2]]]]"#,
+        );
+        assert_eq!(
+            json_to_qir(&identity).unwrap(),
+            json_to_qir(&circuit("[]")).unwrap()
+        );
+
+        for permutation in [
+            r#"[[["q", [0]], ["q", [3]]]]"#,
+            r#"[[["q", [0]], ["q", [1]]]]"#,
+        ] {
+            assert!(matches!(
+                json_to_qir(&circuit(permutation)),
+                Err(TketQirError::Permutation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_permutation_swaps() {
+        for targets in [
+            vec![1, 2, 0],
+            vec![0, 1, 2],
+            vec![3, 2, 1, 0],
+            vec![2, 0, 3, 1],
+        ] {
+            let mut state: Vec<usize> = (0..targets.len()).collect();
+            for (a, b) in permutation_swaps(&targets) {
+                state.swap(a, b);
+            }
+            // `state[j]` is the qubit whose state is on `j`
+            for (i, &target) in targets.iter().enumerate() {
+                assert_eq!(state[target], i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_global_phase() {
+        let inner = r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"type": "SX"}}], "implicit_permutation": [], "phase": "0.5", "qubits": [["q", [0]]]}"#;
+        let json = format!(
+            r#"{{"bits": [["c", [0]]], "commands": [{{"args": [["q", [0]]], "op": {{"params": ["0.5"], "type": "U1"}}}}, {{"args": [["q", [1]]], "op": {{"box": {{"circuit": {}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}}, "type": "CircBox"}}}}, {{"args": [["c", [0]], ["q", [1]]], "op": {{"conditional": {{"op": {{"params": ["1"], "type": "U1"}}, "value": 1, "width": 1}}, "type": "Conditional"}}}}], "implicit_permutation": [], "phase": "a", "qubits": [["q", [0]], ["q", [1]]]}}"#,
+            inner
+        );
+        let circ: Circuit = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            circuit_to_qir(&circ),
+            Err(TketQirError::Phase(ParamError::UnboundSymbol(_)))
+        ));
+
+        // 0.25 from U1, 0.5 + 0.25 from the box and its SX, none from the
+        // conditional U1
+        for box_functions in [false, true] {
+            let options = QirOptions {
+                bindings: Bindings::from([("a".to_string(), 0.25)]),
+                box_functions,
+                ..QirOptions::default()
+            };
+            let ir = circuit_to_qir_with(&circ, &options).expect("Conversion failed.");
+            assert!(ir.contains(r#""tket_global_phase"="1.25""#));
+        }
+
+        let mut circ = circ;
+        circ.phase = "0.0".to_string();
+        circ.commands.clear();
+        let ir = circuit_to_qir(&circ).expect("Conversion failed.");
+        assert!(!ir.contains(GLOBAL_PHASE_ATTRIBUTE));
+    }
+
+    #[test]
+    fn test_barrier_and_phase() {
+        let ir = json_to_qir(
+            r#"{"bits": [["c", [0]]], "commands": [{"args": [["q", [0]], ["c", [0]]], "op": {"signature": ["Q", "C"], "type": "Barrier"}}, {"args": [], "op": {"params": ["0.75"], "type": "Phase"}}], "implicit_permutation": [], "phase": "0.5", "qubits": [["q", [0]]]}"#,
+        )
+        .expect("Conversion failed.");
+        assert!(ir.contains(r#""tket_global_phase"="1.25""#));
+
+        let err = json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"type": "NewGate"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::UnsupportedOp(OpType::Unknown(ref name)),
+                ..
+            } if name == "NewGate"
+        ));
+    }
+
+    #[test]
+    fn test_multi_qubit_gates() {
+        let ir = json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]], ["q", [1]]], "op": {"type": "CZ"}}, {"args": [["q", [0]], ["q", [1]]], "op": {"type": "SWAP"}}, {"args": [["q", [0]], ["q", [1]], ["q", [2]]], "op": {"type": "CCX"}}, {"args": [["q", [1]], ["q", [2]]], "op": {"params": ["0.3"], "type": "ZZPhase"}}, {"args": [["q", [0]], ["q", [2]]], "op": {"type": "ZZMax"}}, {"args": [["q", [0]], ["q", [1]]], "op": {"type": "ECR"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]], ["q", [2]]]}"#,
+        )
+        .expect("Conversion failed.");
+        assert!(ir.contains("__quantum__qis__swap__body"));
+        assert!(ir.contains("__quantum__qis__ccx__body"));
+    }
+
+    #[test]
+    fn test_symbolic_parameters() {
+        let circ: Circuit = serde_json::from_str(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"params": ["0.5*a + pi/4"], "type": "Rz"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        )
+        .unwrap();
+
+        let err = circuit_to_qir(&circ).unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::UnboundSymbol(ref symbol),
+                ..
+            } if symbol == "a"
+        ));
+
+        let options = QirOptions {
+            bindings: Bindings::from([("a".to_string(), 0.5)]),
+            ..QirOptions::default()
+        };
+        circuit_to_qir_with(&circ, &options).expect("Conversion failed.");
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["q", [0]]], "op": {"type": "H"}}, {"args": [["q", [0]]], "op": {"params": ["0.5*"], "type": "Rz"}, "opgroup": "rot"}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        )
+        .unwrap_err();
+        match err {
+            TketQirError::Command {
+                index,
+                opgroup,
+                source,
+            } => {
+                assert_eq!(index, 1);
+                assert_eq!(opgroup.as_deref(), Some("rot"));
+                assert_eq!(source, CommandError::BadParameter("0.5*".to_string()));
+            }
+            err => panic!("unexpected error {}", err),
+        }
+
+        let err = json_to_qir(
+            r#"{"bits": [], "commands": [{"args": [["r", [0]]], "op": {"type": "H"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]]]}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            TketQirError::Command {
+                source: CommandError::UnknownRegister(_),
+                ..
+            }
+        ));
+
+        assert!(matches!(json_to_qir("{}"), Err(TketQirError::Json(_))));
+    }
+}
@@ -0,0 +1,117 @@
+//! Inlining of `CircBox`es into the circuit that uses them.
+
+use std::collections::HashMap;
+
+use crate::circuit::{Circuit, Command, OpBox, OpType, Register};
+use crate::error::CommandError;
+
+/// Commands of the circuit of a `CircBox` applied to `args`, with nested
+/// `CircBox`es inlined as well.
+///
+/// As in pytket, the box's qubits are mapped positionally to the first
+/// arguments and its bits to the remaining ones.
+pub fn inline_circbox(circuit: &Circuit, args: &[Register]) -> Result<Vec<Command>, CommandError> {
+    let expected = circuit.qubits.len() + circuit.bits.len();
+    if args.len() != expected {
+        return Err(CommandError::Arity {
+            op_type: OpType::CircBox,
+            expected,
+            found: args.len(),
+        });
+    }
+    let units: HashMap<&Register, &Register> = circuit
+        .qubits
+        .iter()
+        .chain(&circuit.bits)
+        .zip(args)
+        .collect();
+
+    let mut commands = vec![];
+    for com in &circuit.commands {
+        let args = com
+            .args
+            .iter()
+            .map(|unit| {
+                units
+                    .get(unit)
+                    .map(|&arg| arg.clone())
+                    .ok_or_else(|| CommandError::UnknownRegister(unit.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match (&com.op.op_type, com.op.op_box.as_ref()) {
+            (OpType::CircBox, Some(OpBox::CircBox { circuit, .. })) => {
+                commands.extend(inline_circbox(circuit, &args)?);
+            }
+            (OpType::CircBox, _) => return Err(CommandError::MissingData(OpType::CircBox, "box")),
+            _ => commands.push(Command {
+                op: com.op.clone(),
+                args,
+                opgroup: com.opgroup.clone(),
+                extra: com.extra.clone(),
+            }),
+        }
+    }
+    Ok(commands)
+}
+
+/// Global phases, as Symengine Exprs, of the circuit of a `CircBox` and of
+/// the boxes [`inline_circbox`] inlines into it.
+pub fn inlined_phases(circuit: &Circuit) -> Vec<String> {
+    let mut phases = vec![circuit.phase.clone()];
+    for com in &circuit.commands {
+        if let Some(OpBox::CircBox { circuit, .. }) = com.op.op_box.as_ref() {
+            if com.op.op_type == OpType::CircBox {
+                phases.extend(inlined_phases(circuit));
+            }
+        }
+    }
+    phases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reg(name: &str, index: u64) -> Register {
+        Register(name.to_string(), vec![index])
+    }
+
+    #[test]
+    fn test_nested_boxes() {
+        let inner: Circuit = serde_json::from_str(
+            r#"{"bits": [["c", [0]]], "commands": [{"args": [["q", [0]], ["q", [1]]], "op": {"type": "CX"}}, {"args": [["q", [1]], ["c", [0]]], "op": {"type": "Measure"}}], "implicit_permutation": [], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#,
+        )
+        .unwrap();
+        let outer: Circuit = serde_json::from_str(&format!(
+            r#"{{"bits": [["b", [0]]], "commands": [{{"args": [["a", [0]]], "op": {{"type": "H"}}}}, {{"args": [["a", [1]], ["a", [0]], ["b", [0]]], "op": {{"box": {{"circuit": {}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}}, "type": "CircBox"}}}}], "implicit_permutation": [], "phase": "0.25", "qubits": [["a", [0]], ["a", [1]]]}}"#,
+            serde_json::to_string(&inner).unwrap()
+        ))
+        .unwrap();
+
+        let commands = inline_circbox(&outer, &[reg("q", 3), reg("q", 4), reg("c", 2)])
+            .expect("Inlining failed.");
+        let summary: Vec<_> = commands
+            .iter()
+            .map(|com| (com.op.op_type.clone(), com.args.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (OpType::H, vec![reg("q", 3)]),
+                (OpType::CX, vec![reg("q", 4), reg("q", 3)]),
+                (OpType::Measure, vec![reg("q", 3), reg("c", 2)]),
+            ]
+        );
+
+        assert_eq!(inlined_phases(&outer), vec!["0.25", "0.0"]);
+
+        assert!(matches!(
+            inline_circbox(&outer, &[reg("q", 3)]),
+            Err(CommandError::Arity {
+                expected: 3,
+                found: 1,
+                ..
+            })
+        ));
+    }
+}
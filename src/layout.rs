@@ -0,0 +1,195 @@
+//! Flat indices and QIR names for the qubits and bits of a circuit.
+//!
+//! pytket registers may have any number of dimensions, e.g. `["grid", [1, 2]]`,
+//! whereas QIR addresses qubits and results by a flat index and qirlib names
+//! them by a register name followed by an index. The layout fixes both so that
+//! the exporter and importer agree.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::circuit::{Circuit, Register};
+
+/// Name of a qubit or bit in the generated QIR: a register name, never ending
+/// in a digit, followed by the index in that register.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct QirName {
+    pub register: String,
+    pub index: u64,
+}
+
+impl QirName {
+    /// The name qirlib gives the qubit or result.
+    pub fn key(&self) -> String {
+        format!("{}{}", self.register, self.index)
+    }
+}
+
+/// Flat indices and QIR names of one kind of unit, qubits or bits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnitLayout {
+    units: Vec<Register>,
+    indices: HashMap<Register, usize>,
+    names: Vec<QirName>,
+}
+
+impl UnitLayout {
+    /// Lay out `units` in the given order, which becomes their flat index.
+    pub fn new(units: &[Register]) -> Self {
+        let mut indices = HashMap::new();
+        let mut registers: HashMap<(&str, &[u64]), String> = HashMap::new();
+        let mut taken = HashSet::new();
+        let mut names = vec![];
+        for (flat, unit) in units.iter().enumerate() {
+            indices.insert(unit.clone(), flat);
+            let (prefix, index) = match &unit.1[..] {
+                [prefix @ .., index] => (prefix, *index),
+                [] => (&unit.1[..], 0),
+            };
+            let register = registers
+                .entry((&unit.0, prefix))
+                .or_insert_with(|| {
+                    let mut register = register_name(&unit.0, prefix);
+                    // Different pytket registers may sanitise to the same name
+                    while !taken.insert(register.clone()) {
+                        register.push('_');
+                    }
+                    register
+                })
+                .clone();
+            names.push(QirName { register, index });
+        }
+        UnitLayout {
+            units: units.to_vec(),
+            indices,
+            names,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// Flat index of a unit.
+    pub fn index(&self, unit: &Register) -> Option<usize> {
+        self.indices.get(unit).copied()
+    }
+
+    /// Unit at a flat index.
+    pub fn unit(&self, index: usize) -> Option<&Register> {
+        self.units.get(index)
+    }
+
+    /// QIR name of a unit.
+    pub fn name(&self, unit: &Register) -> Option<&QirName> {
+        self.index(unit).map(|index| &self.names[index])
+    }
+
+    /// QIR names of all units, by flat index.
+    pub fn names(&self) -> &[QirName] {
+        &self.names
+    }
+
+    /// QIR register names with their sizes, i.e. the largest index plus one.
+    pub fn registers(&self) -> Vec<(String, u64)> {
+        let mut registers: Vec<(String, u64)> = vec![];
+        for name in &self.names {
+            match registers.iter_mut().find(|(reg, _)| *reg == name.register) {
+                Some((_, size)) => *size = std::cmp::max(*size, name.index + 1),
+                None => registers.push((name.register.clone(), name.index + 1)),
+            }
+        }
+        registers
+    }
+}
+
+/// QIR register name for the units of pytket register `name` whose indices
+/// start with `prefix`.
+fn register_name(name: &str, prefix: &[u64]) -> String {
+    let mut register: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    for index in prefix {
+        register.push_str(&format!("_{}", index));
+    }
+    // The index is appended to the register name, so it must not end in a digit
+    if register.is_empty() || register.ends_with(|c: char| c.is_ascii_digit()) {
+        register.push('_');
+    }
+    register
+}
+
+/// Layout of the qubits and bits of a circuit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegisterLayout {
+    pub qubits: UnitLayout,
+    pub bits: UnitLayout,
+}
+
+impl RegisterLayout {
+    pub fn new(circ: &Circuit) -> Self {
+        RegisterLayout {
+            qubits: UnitLayout::new(&circ.qubits),
+            bits: UnitLayout::new(&circ.bits),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reg(name: &str, index: &[u64]) -> Register {
+        Register(name.to_string(), index.to_vec())
+    }
+
+    #[test]
+    fn test_one_dimensional() {
+        let layout = UnitLayout::new(&[reg("q", &[0]), reg("q", &[1]), reg("a", &[3])]);
+        assert_eq!(layout.index(&reg("q", &[1])), Some(1));
+        assert_eq!(layout.unit(2), Some(&reg("a", &[3])));
+        assert_eq!(layout.name(&reg("q", &[1])).unwrap().key(), "q1");
+        assert_eq!(
+            layout.registers(),
+            vec![("q".to_string(), 2), ("a".to_string(), 4)]
+        );
+        assert_eq!(layout.index(&reg("q", &[2])), None);
+    }
+
+    #[test]
+    fn test_multi_dimensional() {
+        let units = [
+            reg("grid", &[0, 0]),
+            reg("grid", &[0, 1]),
+            reg("grid", &[1, 0]),
+            reg("grid", &[1, 1]),
+        ];
+        let layout = UnitLayout::new(&units);
+        let keys: Vec<String> = layout.names().iter().map(QirName::key).collect();
+        assert_eq!(keys, vec!["grid_0_0", "grid_0_1", "grid_1_0", "grid_1_1"]);
+        assert_eq!(layout.index(&reg("grid", &[1, 0])), Some(2));
+        assert_eq!(
+            layout.registers(),
+            vec![("grid_0_".to_string(), 2), ("grid_1_".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        let units = [
+            reg("q", &[11]),
+            reg("q1", &[1]),
+            reg("q", &[1, 1]),
+            reg("q_1", &[1]),
+            reg("q-1", &[1]),
+            reg("scalar", &[]),
+        ];
+        let layout = UnitLayout::new(&units);
+        let keys: HashSet<String> = layout.names().iter().map(QirName::key).collect();
+        assert_eq!(keys.len(), units.len());
+    }
+}
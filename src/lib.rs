@@ -0,0 +1,11 @@
+mod base;
+mod classical;
+pub mod circuit;
+pub mod decompose;
+pub mod error;
+pub mod generate;
+pub mod inline;
+pub mod layout;
+pub mod params;
+pub mod parse;
+pub mod synthesis;
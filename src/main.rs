@@ -1,28 +1,222 @@
-// mod array1d;
-// mod basic_values;
-mod circuit;
-mod generate;
-mod parse;
-// mod emit;
-use qirlib::generation::emit::ir;
-// use pyqir_generator::emitemit::{get_ir_string, write_circ_to_file};
-
-fn read_json_str(circ_s: &str) -> String {
-    let p: circuit::Circuit = serde_json::from_str(circ_s).unwrap();
-    ir(&p).unwrap()
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{ArgEnum, Parser, Subcommand};
+
+use tket_qir::circuit::{Circuit, Patch};
+use tket_qir::generate::{
+    circuit_to_qir_with, ir_to_bitcode, PermutationMode, Profile, QirOptions,
+};
+use tket_qir::parse::{function_to_circuit, parse_qir_file, ModuleExtension};
+
+/// Convert between pytket JSON circuits and QIR.
+#[derive(Parser)]
+#[clap(name = "tket-qir", version)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Generate QIR from a pytket JSON circuit.
+    ToQir {
+        /// Circuit JSON file, `-` or nothing for stdin.
+        input: Option<PathBuf>,
+        /// Output file, stdout if not given.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Output format, inferred from the output extension if not given.
+        #[clap(long, arg_enum)]
+        emit: Option<Format>,
+        /// Name of the generated entry point.
+        #[clap(long)]
+        entry_point: Option<String>,
+        /// Value for a free symbol of the circuit, as `name=value` in half-turns.
+        #[clap(long = "bind", value_name = "NAME=VALUE", parse(try_from_str = parse_binding))]
+        bindings: Vec<(String, f64)>,
+        /// Emit each CircBox as a function instead of inlining it.
+        #[clap(long)]
+        box_functions: bool,
+        /// How to apply the circuit's implicit qubit permutation, by default
+        /// with SWAPs in the full profile and relabelling in the others.
+        #[clap(long, arg_enum)]
+        permutation: Option<Permutation>,
+        /// QIR profile of the output.
+        #[clap(long, arg_enum, default_value = "full")]
+        profile: QirProfile,
+    },
+    /// Import a QIR program, textual or bitcode, as a pytket JSON circuit.
+    FromQir {
+        /// QIR file, `-` or nothing for stdin.
+        input: Option<PathBuf>,
+        /// Output file, stdout if not given.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// Function to import, the module's entry point if not given.
+        #[clap(long)]
+        entry_point: Option<String>,
+    },
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+enum Format {
+    /// Textual LLVM IR.
+    Ll,
+    /// LLVM bitcode.
+    Bc,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+enum Permutation {
+    /// Give each qubit the static address of the qubit its state ends up on,
+    /// or append SWAPs in the full profile.
+    Relabel,
+    /// Append SWAPs moving each state to its qubit.
+    Swaps,
+}
+
+#[derive(ArgEnum, Clone, Copy, PartialEq)]
+enum QirProfile {
+    /// The full runtime, with dynamic allocation and branches.
+    Full,
+    /// Static addresses and no branches, as hardware providers accept.
+    Base,
+    /// Static addresses with branches and computations on measurement results.
+    Adaptive,
+}
+
+fn parse_binding(binding: &str) -> Result<(String, f64), String> {
+    let (name, value) = binding
+        .split_once('=')
+        .ok_or_else(|| format!("Expected NAME=VALUE, found {}", binding))?;
+    let value = value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", name, value))?;
+    Ok((name.to_string(), value))
 }
+
+fn read_input(input: &Option<PathBuf>) -> Result<Vec<u8>, String> {
+    match input {
+        Some(path) if path != Path::new("-") => {
+            fs::read(path).map_err(|err| format!("Reading {} has failed: {}", path.display(), err))
+        }
+        _ => {
+            let mut buf = vec![];
+            io::stdin()
+                .read_to_end(&mut buf)
+                .map_err(|err| format!("Reading stdin has failed: {}", err))?;
+            Ok(buf)
+        }
+    }
+}
+
+fn write_output(output: &Option<PathBuf>, bytes: &[u8]) -> Result<(), String> {
+    match output {
+        Some(path) => fs::write(path, bytes)
+            .map_err(|err| format!("Writing {} has failed: {}", path.display(), err)),
+        None => io::stdout()
+            .write_all(bytes)
+            .map_err(|err| format!("Writing stdout has failed: {}", err)),
+    }
+}
+
+fn to_qir(
+    input: &Option<PathBuf>,
+    output: &Option<PathBuf>,
+    emit: Option<Format>,
+    entry_point: Option<String>,
+    options: &QirOptions,
+) -> Result<(), String> {
+    let mut circ: Circuit =
+        serde_json::from_slice(&read_input(input)?).map_err(|err| err.to_string())?;
+    if let Some(name) = entry_point {
+        circ.name = Patch::Value(name);
+    }
+    let format = emit.unwrap_or(match output {
+        Some(path) if path.extension().map_or(false, |ext| ext == "bc") => Format::Bc,
+        _ => Format::Ll,
+    });
+
+    let ir = circuit_to_qir_with(&circ, options).map_err(|err| err.to_string())?;
+    match format {
+        Format::Ll => write_output(output, ir.as_bytes()),
+        Format::Bc => write_output(output, &ir_to_bitcode(&ir)?),
+    }
+}
+
+fn from_qir(
+    input: &Option<PathBuf>,
+    output: &Option<PathBuf>,
+    entry_point: Option<String>,
+) -> Result<(), String> {
+    // llvm-ir only parses from files, so stdin goes through a temporary one,
+    // removed when dropped.
+    let module = match input {
+        Some(path) if path != Path::new("-") => parse_qir_file(path)?,
+        _ => {
+            let mut file = tempfile::Builder::new()
+                .prefix("tket-qir-")
+                .suffix(".qir")
+                .tempfile()
+                .map_err(|err| format!("Creating a temporary file has failed: {}", err))?;
+            file.write_all(&read_input(input)?)
+                .map_err(|err| format!("Writing a temporary file has failed: {}", err))?;
+            parse_qir_file(file.path())?
+        }
+    };
+
+    let func = match &entry_point {
+        Some(name) => module.get_func_by_name(name),
+        None => module.get_entry_point(),
+    }
+    .ok_or_else(|| match &entry_point {
+        Some(name) => format!("Function {} not found.", name),
+        None => "No entry point found.".to_string(),
+    })?;
+    let circ = function_to_circuit(func).map_err(|err| err.to_string())?;
+
+    let json = serde_json::to_string(&circ).map_err(|err| err.to_string())?;
+    write_output(output, json.as_bytes())
+}
+
 fn main() {
-    println!(
-        "{}",
-        read_json_str(
-            r#"{"bits": [["c", [0]], ["c", [1]]], "commands": [{"args": [["q", [0]]], "op": {"type": "H"}}, {"args": [["q", [0]], ["q", [1]]], "op": {"type": "CX"}}, {"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}, {"args": [["q", [1]]], "op": {"params": ["0.2"], "type": "Rz"}}, {"args": [["q", [1]], ["c", [1]]], "op": {"type": "Measure"}}], "implicit_permutation": [[["q", [0]], ["q", [0]]], [["q", [1]], ["q", [1]]]], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#
-        )
-    );
-    // dbg!(get_ir_string(&p));
-    println!(
-        "{}",
-        read_json_str(
-            r#"{"bits": [["c", [0]], ["c", [1]]], "commands": [{"args": [["q", [0]]], "op": {"type": "X"}}, {"args": [["q", [0]], ["c", [0]]], "op": {"type": "Measure"}}, {"args": [["c", [0]], ["q", [1]]], "op": {"conditional": {"op": {"type": "Z"}, "value": 0, "width": 1}, "type": "Conditional"}}, {"args": [["c", [1]], ["q", [0]], ["q", [1]]], "op": {"conditional": {"op": {"box": {"circuit": {"bits": [], "commands": [{"args": [["q", [0]], ["q", [1]]], "op": {"type": "CX"}}], "implicit_permutation": [[["q", [0]], ["q", [0]]], [["q", [1]], ["q", [1]]]], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}, "id": "91810268-1b06-47b4-8609-992d066b56f2", "type": "CircBox"}, "type": "CircBox"}, "value": 1, "width": 1}, "type": "Conditional"}}], "implicit_permutation": [[["q", [0]], ["q", [0]]], [["q", [1]], ["q", [1]]]], "phase": "0.0", "qubits": [["q", [0]], ["q", [1]]]}"#
-        )
-    );
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Commands::ToQir {
+            input,
+            output,
+            emit,
+            entry_point,
+            bindings,
+            box_functions,
+            permutation,
+            profile,
+        } => {
+            let options = QirOptions {
+                bindings: bindings.into_iter().collect(),
+                box_functions,
+                permutation: permutation.map(|permutation| match permutation {
+                    Permutation::Relabel => PermutationMode::Relabel,
+                    Permutation::Swaps => PermutationMode::Swaps,
+                }),
+                profile: match profile {
+                    QirProfile::Full => Profile::Full,
+                    QirProfile::Base => Profile::Base,
+                    QirProfile::Adaptive => Profile::Adaptive,
+                },
+            };
+            to_qir(&input, &output, emit, entry_point, &options)
+        }
+        Commands::FromQir {
+            input,
+            output,
+            entry_point,
+        } => from_qir(&input, &output, entry_point),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,455 @@
+//! Parameter expressions as serialised by pytket, i.e. SymEngine expressions
+//! in half-turns such as `"0.5*a"`, `"pi/4"` or `"-0.25"`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Values for the free symbols of a circuit.
+pub type Bindings = HashMap<String, f64>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Symbol(String),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Pow => "**",
+        })
+    }
+}
+
+/// Writes the expression in pytket's syntax, with operands which are
+/// operations themselves in parentheses.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operand = |f: &mut fmt::Formatter<'_>, expr: &Expr| match expr {
+            Expr::Neg(_) | Expr::Binary(..) => write!(f, "({})", expr),
+            _ => write!(f, "{}", expr),
+        };
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Symbol(name) => f.write_str(name),
+            Expr::Neg(inner) => {
+                f.write_str("-")?;
+                operand(f, inner)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                operand(f, lhs)?;
+                write!(f, " {} ", op)?;
+                operand(f, rhs)
+            }
+            Expr::Call(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamError {
+    /// The string is not a valid expression.
+    Syntax(String),
+    /// A function which is not part of the grammar.
+    UnknownFunction(String),
+    /// A free symbol without a value.
+    UnboundSymbol(String),
+    /// An operation whose value is infinite or NaN, e.g. a division by zero.
+    NotFinite(String),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Syntax(expr) => write!(f, "invalid expression {:?}", expr),
+            ParamError::UnknownFunction(name) => write!(f, "unknown function {}", name),
+            ParamError::UnboundSymbol(name) => write!(f, "unbound symbol {}", name),
+            ParamError::NotFinite(expr) => write!(f, "{} is not finite", expr),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(char),
+    LParen,
+    RParen,
+    Comma,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, ParamError> {
+    let syntax = || ParamError::Syntax(s.to_string());
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            _ if c.is_whitespace() => i += 1,
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                // Exponent, e.g. 1e-05
+                if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                    let mut j = i + 1;
+                    if j < chars.len() && (chars[j] == '+' || chars[j] == '-') {
+                        j += 1;
+                    }
+                    if j < chars.len() && chars[j].is_ascii_digit() {
+                        i = j;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let number: String = chars[start..i].iter().collect();
+                tokens.push(Token::Number(number.parse().map_err(|_| syntax())?));
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                tokens.push(Token::Op('^'));
+                i += 2;
+            }
+            '+' | '-' | '*' | '/' | '^' => {
+                tokens.push(Token::Op(c));
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            _ => return Err(syntax()),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens of one expression.
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn syntax(&self) -> ParamError {
+        ParamError::Syntax(self.source.to_string())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParamError> {
+        if self.next() == Some(token) {
+            Ok(())
+        } else {
+            Err(self.syntax())
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Expr, ParamError> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            let op = if op == '+' { BinOp::Add } else { BinOp::Sub };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Result<Expr, ParamError> {
+        let mut lhs = self.unary()?;
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.pos += 1;
+            let op = if op == '*' { BinOp::Mul } else { BinOp::Div };
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    // unary := ('-' | '+') unary | power
+    fn unary(&mut self) -> Result<Expr, ParamError> {
+        match self.peek() {
+            Some(Token::Op('-')) => {
+                self.pos += 1;
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            }
+            Some(Token::Op('+')) => {
+                self.pos += 1;
+                self.unary()
+            }
+            _ => self.power(),
+        }
+    }
+
+    // power := atom ('^' unary)?, right associative
+    fn power(&mut self) -> Result<Expr, ParamError> {
+        let base = self.atom()?;
+        if let Some(Token::Op('^')) = self.peek() {
+            self.pos += 1;
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(BinOp::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    // atom := number | symbol | function '(' expr (',' expr)* ')' | '(' expr ')'
+    fn atom(&mut self) -> Result<Expr, ParamError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Number(value)),
+            Some(Token::Ident(name)) => {
+                if self.peek() != Some(&Token::LParen) {
+                    return Ok(Expr::Symbol(name));
+                }
+                self.pos += 1;
+                let mut args = vec![self.expr()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    args.push(self.expr()?);
+                }
+                self.expect(Token::RParen)?;
+                Ok(Expr::Call(name, args))
+            }
+            Some(Token::LParen) => {
+                let inner = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(inner)
+            }
+            _ => Err(self.syntax()),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParamError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            source: s,
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.pos != parser.tokens.len() {
+            return Err(parser.syntax());
+        }
+        Ok(expr)
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression, looking up free symbols in `bindings`. Angles
+    /// must be finite, so is the value of each operation.
+    pub fn eval(&self, bindings: &Bindings) -> Result<f64, ParamError> {
+        let value = self.eval_unchecked(bindings)?;
+        if value.is_finite() {
+            Ok(value)
+        } else {
+            Err(ParamError::NotFinite(self.to_string()))
+        }
+    }
+
+    fn eval_unchecked(&self, bindings: &Bindings) -> Result<f64, ParamError> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Symbol(name) => match (bindings.get(name), &name[..]) {
+                (Some(value), _) => Ok(*value),
+                (None, "pi") => Ok(std::f64::consts::PI),
+                (None, "E") => Ok(std::f64::consts::E),
+                (None, _) => Err(ParamError::UnboundSymbol(name.clone())),
+            },
+            Expr::Neg(inner) => Ok(-inner.eval(bindings)?),
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(bindings)?, rhs.eval(bindings)?);
+                Ok(match op {
+                    BinOp::Add => lhs + rhs,
+                    BinOp::Sub => lhs - rhs,
+                    BinOp::Mul => lhs * rhs,
+                    BinOp::Div => lhs / rhs,
+                    BinOp::Pow => lhs.powf(rhs),
+                })
+            }
+            Expr::Call(name, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(bindings))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let unary = |f: fn(f64) -> f64| match args[..] {
+                    [x] => Ok(f(x)),
+                    _ => Err(ParamError::Syntax(self.to_string())),
+                };
+                match &name[..] {
+                    "sin" => unary(f64::sin),
+                    "cos" => unary(f64::cos),
+                    "tan" => unary(f64::tan),
+                    "asin" => unary(f64::asin),
+                    "acos" => unary(f64::acos),
+                    "atan" => unary(f64::atan),
+                    "sinh" => unary(f64::sinh),
+                    "cosh" => unary(f64::cosh),
+                    "tanh" => unary(f64::tanh),
+                    "exp" => unary(f64::exp),
+                    "log" => unary(f64::ln),
+                    "sqrt" => unary(f64::sqrt),
+                    "abs" => unary(f64::abs),
+                    _ => Err(ParamError::UnknownFunction(name.clone())),
+                }
+            }
+        }
+    }
+}
+
+/// Parse and evaluate a pytket parameter.
+pub fn eval_param(param: &str, bindings: &Bindings) -> Result<f64, ParamError> {
+    param.parse::<Expr>()?.eval(bindings)
+}
+
+/// Convert a pytket angle, in half-turns, to the radians QIR rotations expect.
+pub fn half_turns_to_radians(angle: f64) -> f64 {
+    angle * std::f64::consts::PI
+}
+
+/// Convert an angle in radians from QIR to pytket half-turns.
+pub fn radians_to_half_turns(angle: f64) -> f64 {
+    angle / std::f64::consts::PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn eval(param: &str) -> f64 {
+        eval_param(param, &Bindings::new()).unwrap()
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(eval("0.2"), 0.2);
+        assert_eq!(eval("-0.25"), -0.25);
+        assert_eq!(eval("1e-05"), 1e-05);
+        assert_eq!(eval("2.5E+2"), 250.0);
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(eval("1 + 2*3"), 7.0);
+        assert_eq!(eval("(1 + 2)*3"), 9.0);
+        assert_eq!(eval("1 - 2 - 3"), -4.0);
+        assert_eq!(eval("8/2/2"), 2.0);
+        assert_eq!(eval("2^3^2"), 512.0);
+        assert_eq!(eval("2**3"), 8.0);
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("2^-1"), 0.5);
+    }
+
+    #[test]
+    fn test_constants_and_functions() {
+        assert_eq!(eval("pi/4"), PI / 4.0);
+        assert!((eval("sin(pi/2)") - 1.0).abs() < 1e-12);
+        assert_eq!(eval("sqrt(4)*cos(0)"), 2.0);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let expr: Expr = "0.5*a + b/2".parse().unwrap();
+        assert_eq!(
+            expr.eval(&Bindings::new()),
+            Err(ParamError::UnboundSymbol("a".to_string()))
+        );
+        let bindings = Bindings::from([("a".to_string(), 1.0), ("b".to_string(), 0.5)]);
+        assert_eq!(expr.eval(&bindings), Ok(0.75));
+    }
+
+    #[test]
+    fn test_angle_conversion() {
+        assert_eq!(half_turns_to_radians(0.5), PI / 2.0);
+        assert_eq!(radians_to_half_turns(-PI), -1.0);
+        for angle in [0.0, 0.1, -0.25, 1.0, 3.7, 1e-9] {
+            assert!((radians_to_half_turns(half_turns_to_radians(angle)) - angle).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!("0.5*".parse::<Expr>(), Err(ParamError::Syntax(_))));
+        assert!(matches!("(1".parse::<Expr>(), Err(ParamError::Syntax(_))));
+        assert!(matches!("1 2".parse::<Expr>(), Err(ParamError::Syntax(_))));
+        assert!(matches!(
+            "1 $ 2".parse::<Expr>(),
+            Err(ParamError::Syntax(_))
+        ));
+        assert_eq!(
+            eval_param("foo(1)", &Bindings::new()),
+            Err(ParamError::UnknownFunction("foo".to_string()))
+        );
+        let bindings = Bindings::from([("a".to_string(), 0.5)]);
+        for (param, operation) in [
+            ("a/0", "a / 0"),
+            ("log(0)", "log(0)"),
+            ("sqrt(-1)", "sqrt(-1)"),
+            ("(a/0)*0", "a / 0"),
+            ("2**(a*4096)", "2 ** (a * 4096)"),
+        ] {
+            assert_eq!(
+                eval_param(param, &bindings),
+                Err(ParamError::NotFinite(operation.to_string()))
+            );
+        }
+    }
+}
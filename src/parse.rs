@@ -4,6 +4,8 @@ use inkwell::context::Context;
 use inkwell::module::Module;
 use inkwell::values::FunctionValue;
 
+use std::collections::HashMap;
+use std::fmt;
 use std::path::Path;
 
 use llvm_ir::instruction::{Instruction, InlineAssembly};
@@ -11,12 +13,15 @@ use llvm_ir::operand::Operand;
 
 use either::Either;
 
-use crate::circuit::{OpType, Circuit};
+use crate::circuit::{Circuit, Command, Fields, OpType, Operation, Patch, Permutation, Register};
+use crate::generate::GLOBAL_PHASE_ATTRIBUTE;
+use crate::layout::RegisterLayout;
+use crate::params::radians_to_half_turns;
 
 
 pub trait ModuleExtension {
     fn get_func_by_name(&self, name: &str) -> Vec<&llvm_ir::Function>;
-
+    fn get_entry_point(&self) -> Option<&llvm_ir::Function>;
 }
 
 impl ModuleExtension for llvm_ir::Module {
@@ -33,6 +38,18 @@ impl ModuleExtension for llvm_ir::Module {
 	    })
 	    .collect()
     }
+
+    fn get_entry_point(&self) -> Option<&llvm_ir::Function> {
+	// QIR marks the entry point with "EntryPoint", older Q# output
+	// with "InteropFriendly".
+	["EntryPoint", "InteropFriendly"]
+	    .iter()
+	    .find_map(|attr| ModuleExtension::get_func_by_name(self, attr).into_iter().next())
+	    .or_else(|| match self.functions.as_slice() {
+		[only] => Some(only),
+		_ => None,
+	    })
+    }
 }
 
 
@@ -47,7 +64,7 @@ impl FunctionExtension for llvm_ir::Function {
 	    match attr {
 		llvm_ir::function::FunctionAttribute::StringAttribute { kind, value } => {
                     if kind.to_string().eq(name) {
-                        Some(value.to_string());
+			return Some(value.to_string());
                     }
                 },
                 _ => continue,
@@ -177,45 +194,308 @@ fn match_operand(operand: &Operand) -> Option<&llvm_ir::constant::ConstantRef> {
     }
 }
 
-fn match_to_optype(qir_optype: &str) -> Option<OpType> {
+/// Errors raised while importing a QIR entry point as a pytket circuit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportError {
+    /// No function in the module is marked as an entry point.
+    NoEntryPoint,
+    /// A call to a function with no pytket equivalent.
+    UnsupportedCall(String),
+    /// A call whose arguments do not match the instruction signature.
+    UnexpectedArguments(String),
+    /// An operand which is neither a static nor an allocated qubit/result.
+    UnsupportedOperand(String),
+    /// The entry point branches, which a flat circuit cannot express.
+    UnsupportedControlFlow(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+	match self {
+	    ImportError::NoEntryPoint => write!(f, "no entry point found in module"),
+	    ImportError::UnsupportedCall(name) => write!(f, "unsupported call to {}", name),
+	    ImportError::UnexpectedArguments(name) => {
+		write!(f, "unexpected arguments in call to {}", name)
+	    }
+	    ImportError::UnsupportedOperand(op) => write!(f, "unsupported operand {}", op),
+	    ImportError::UnsupportedControlFlow(func) => {
+		write!(f, "unsupported control flow in {}", func)
+	    }
+	}
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Split a QIS function name into its gate and functor, e.g.
+/// `__quantum__qis__t__adj` gives `("t", "adj")`.
+fn split_qis_name(name: &str) -> Option<(String, &str)> {
+    let rest = name
+	.strip_prefix("__quantum__qis__")
+	.or_else(|| name.strip_prefix("Microsoft__Quantum__Intrinsic__"))?;
+    let (gate, functor) = rest.rsplit_once("__")?;
+    Some((gate.to_lowercase(), functor))
+}
+
+fn match_to_optype(qir_optype: &str, functor: &str) -> Option<OpType> {
+    match (qir_optype, functor) {
+	("h", "body") => Some(OpType::H),
+	("x", "body") => Some(OpType::X),
+	("y", "body") => Some(OpType::Y),
+	("z", "body") => Some(OpType::Z),
+	("s", "body") => Some(OpType::S),
+	("s", "adj") => Some(OpType::Sdg),
+	("t", "body") => Some(OpType::T),
+	("t", "adj") => Some(OpType::Tdg),
+	("rx", "body") => Some(OpType::Rx),
+	("ry", "body") => Some(OpType::Ry),
+	("rz", "body") => Some(OpType::Rz),
+	("reset", "body") => Some(OpType::Reset),
+	("cnot" | "cx", "body") => Some(OpType::CX),
+	("cz", "body") => Some(OpType::CZ),
+	("swap", "body") => Some(OpType::SWAP),
+	("ccx" | "ccnot", "body") => Some(OpType::CCX),
+	("mz" | "m", "body") => Some(OpType::Measure),
+	_ => None,
+    }
+}
 
-    match qir_optype {
-	"h" => Some(OpType::H),
-	"cnot" => Some(OpType::CX),
-	"mz" => Some(OpType::Measure),
+/// Index of a statically allocated qubit or result, i.e. `null` or
+/// `inttoptr (i64 n to %Qubit*)`.
+fn static_index(operand: &Operand) -> Option<u64> {
+    match match_operand(operand)?.as_ref() {
+	llvm_ir::Constant::Null(_) => Some(0),
+	llvm_ir::Constant::IntToPtr(p) => match p.operand.as_ref() {
+	    llvm_ir::Constant::Int { bits: _, value } => Some(*value),
+	    _ => None,
+	},
 	_ => None,
     }
+}
 
+/// Book-keeping for the qubits and results met while walking an entry point.
+#[derive(Default)]
+struct Importer {
+    // Qubits and results held in local values rather than static addresses.
+    local_qubits: HashMap<llvm_ir::Name, u64>,
+    local_results: HashMap<llvm_ir::Name, u64>,
+    n_qubits: u64,
+    n_results: u64,
+    commands: Vec<Command>,
+    // Units to use for flat indices, if the circuit was exported from pytket.
+    layout: RegisterLayout,
 }
 
-fn to_command(instruction: &Instruction) {
-    let mut func_signature = String::new();
-    if let Instruction::Call(call) = instruction {
-	// println!("{}", call);
-	if let Either::Right(operand) = &call.function {
-	    // println!("{}", operand);
-	    func_signature = operand.to_string();
+impl Importer {
+    fn index(
+	operand: &Operand,
+	locals: &HashMap<llvm_ir::Name, u64>,
+	count: &mut u64,
+    ) -> Result<u64, ImportError> {
+	let index = match operand {
+	    Operand::LocalOperand { name, ty: _ } => locals.get(name).copied(),
+	    _ => static_index(operand),
 	}
+	.ok_or_else(|| ImportError::UnsupportedOperand(operand.to_string()))?;
+	*count = std::cmp::max(*count, index + 1);
+	Ok(index)
     }
 
-    let split_signature: Vec<&str> = func_signature.split("__").collect();
-    // println!("{:?}", split_signature);
+    fn qubit(&mut self, operand: &Operand) -> Result<Register, ImportError> {
+	let index = Importer::index(operand, &self.local_qubits, &mut self.n_qubits)?;
+	Ok(self.qubit_at(index))
+    }
 
-    let optype = match_to_optype(split_signature[3]).unwrap();
+    fn qubit_at(&self, index: u64) -> Register {
+	match self.layout.qubits.unit(index as usize) {
+	    Some(unit) => unit.clone(),
+	    None => Register("q".to_string(), vec![index]),
+	}
+    }
 
-    println!("{:?}", optype);
+    fn result(&mut self, operand: &Operand) -> Result<Register, ImportError> {
+	let index = Importer::index(operand, &self.local_results, &mut self.n_results)?;
+	Ok(self.result_at(index))
+    }
 
-    let mut args = String::new();
-    if let Instruction::Call(call) = instruction {
-	// println!("{}", call);
-	let arguments = &call.arguments;
-	if let Operand::ConstantOperand(operand) = &arguments[0].0 {
-	    // println!("{}", operand);
-	    args = operand.to_string();
+    fn result_at(&self, index: u64) -> Register {
+	match self.layout.bits.unit(index as usize) {
+	    Some(unit) => unit.clone(),
+	    None => Register("c".to_string(), vec![index]),
 	}
     }
 
-    println!("{:?}", args);
+    // QIR angles are in radians, pytket ones in half-turns.
+    fn angle(operand: &Operand) -> Result<String, ImportError> {
+	match match_operand(operand).map(|c| c.as_ref()) {
+	    Some(llvm_ir::Constant::Float(llvm_ir::constant::Float::Double(angle))) => {
+		Ok(radians_to_half_turns(*angle).to_string())
+	    }
+	    _ => Err(ImportError::UnsupportedOperand(operand.to_string())),
+	}
+    }
+
+    fn to_command(&mut self, call: &llvm_ir::instruction::Call) -> Result<(), ImportError> {
+	let name = call
+	    .get_func_name()
+	    .ok_or_else(|| ImportError::UnsupportedCall(call.to_string()))?
+	    .as_string();
+
+	if name == "__quantum__rt__qubit_allocate" {
+	    if let Some(dest) = &call.dest {
+		self.local_qubits.insert(dest.clone(), self.n_qubits);
+		self.n_qubits += 1;
+	    }
+	    return Ok(());
+	}
+	// Runtime calls manage memory and record output, none of them act on qubits.
+	if name.starts_with("__quantum__rt__") {
+	    return Ok(());
+	}
+
+	let op_type = split_qis_name(&name)
+	    .and_then(|(gate, functor)| match_to_optype(&gate, functor))
+	    .ok_or_else(|| ImportError::UnsupportedCall(name.clone()))?;
+	let operands: Vec<&Operand> = call.arguments.iter().map(|(op, _)| op).collect();
+
+	let (params, args) = match (&op_type, &operands[..]) {
+	    (OpType::Rx | OpType::Ry | OpType::Rz, [angle, qubit]) => {
+		(Some(vec![Importer::angle(angle)?]), vec![self.qubit(qubit)?])
+	    }
+	    (OpType::Measure, [qubit, result]) => {
+		(None, vec![self.qubit(qubit)?, self.result(result)?])
+	    }
+	    (OpType::Measure, [qubit]) => {
+		// Dynamic measurement, the result is returned rather than passed in.
+		let dest = call
+		    .dest
+		    .clone()
+		    .ok_or_else(|| ImportError::UnexpectedArguments(name.clone()))?;
+		self.local_results.insert(dest, self.n_results);
+		let bit = Register("c".to_string(), vec![self.n_results]);
+		self.n_results += 1;
+		(None, vec![self.qubit(qubit)?, bit])
+	    }
+	    (OpType::Rx | OpType::Ry | OpType::Rz | OpType::Measure, _) => {
+		return Err(ImportError::UnexpectedArguments(name))
+	    }
+	    (OpType::CCX, [_, _, _])
+	    | (OpType::CX | OpType::CZ | OpType::SWAP, [_, _])
+	    | (
+		OpType::H
+		| OpType::X
+		| OpType::Y
+		| OpType::Z
+		| OpType::S
+		| OpType::Sdg
+		| OpType::T
+		| OpType::Tdg
+		| OpType::Reset,
+		[_],
+	    ) => (
+		None,
+		operands
+		    .iter()
+		    .map(|qubit| self.qubit(qubit))
+		    .collect::<Result<Vec<_>, _>>()?,
+	    ),
+	    _ => return Err(ImportError::UnexpectedArguments(name)),
+	};
+
+	self.commands.push(Command {
+	    op: Operation {
+		op_type,
+		n_qb: Patch::Missing,
+		params: params.map_or(Patch::Missing, Patch::Value),
+		op_box: Patch::Missing,
+		signature: Patch::Missing,
+		conditional: Patch::Missing,
+		classical: Patch::Missing,
+		extra: Fields::new(),
+	    },
+	    args,
+	    opgroup: Patch::Missing,
+	    extra: Fields::new(),
+	});
+	Ok(())
+    }
+}
+
+/// Convert the entry point of a QIR module into a pytket circuit.
+///
+/// Qubits are placed in the default register `q` and results in `c`, indexed
+/// by their static address or by order of allocation.
+pub fn qir_to_circuit(module: &llvm_ir::Module) -> Result<Circuit, ImportError> {
+    qir_to_circuit_with_layout(module, &RegisterLayout::default())
+}
+
+/// Convert the entry point of a QIR module into a pytket circuit, mapping
+/// flat indices back to the units of `layout`.
+///
+/// Indices beyond the layout fall back to the default registers, as in
+/// [`qir_to_circuit`].
+pub fn qir_to_circuit_with_layout(
+    module: &llvm_ir::Module,
+    layout: &RegisterLayout,
+) -> Result<Circuit, ImportError> {
+    let entry_point = module.get_entry_point().ok_or(ImportError::NoEntryPoint)?;
+    function_to_circuit_with_layout(entry_point, layout)
+}
+
+/// Convert a single QIR function into a pytket circuit, see [`qir_to_circuit`].
+pub fn function_to_circuit(entry_point: &llvm_ir::Function) -> Result<Circuit, ImportError> {
+    function_to_circuit_with_layout(entry_point, &RegisterLayout::default())
+}
+
+/// Convert a single QIR function into a pytket circuit, see
+/// [`qir_to_circuit_with_layout`].
+pub fn function_to_circuit_with_layout(
+    entry_point: &llvm_ir::Function,
+    layout: &RegisterLayout,
+) -> Result<Circuit, ImportError> {
+    let mut importer = Importer {
+	layout: layout.clone(),
+	..Importer::default()
+    };
+    for block in &entry_point.basic_blocks {
+	if !matches!(block.term, llvm_ir::Terminator::Ret(_)) {
+	    return Err(ImportError::UnsupportedControlFlow(entry_point.name.clone()));
+	}
+	for instr in &block.instrs {
+	    if let Some(call) = match_call(instr) {
+		importer.to_command(call)?;
+	    }
+	}
+    }
+
+    let required = |attr: &str| {
+	entry_point
+	    .get_attr_by_name(attr)
+	    .and_then(|value| value.parse::<u64>().ok())
+	    .unwrap_or(0)
+    };
+    let n_qubits = std::cmp::max(importer.n_qubits, required("requiredQubits"));
+    let n_results = std::cmp::max(importer.n_results, required("requiredResults"));
+
+    let qubits: Vec<Register> = (0..n_qubits).map(|i| importer.qubit_at(i)).collect();
+    let bits = (0..n_results).map(|i| importer.result_at(i)).collect();
+    let implicit_permutation = qubits
+	.iter()
+	.map(|qb| Permutation(qb.clone(), qb.clone()))
+	.collect();
+    let phase = entry_point
+	.get_attr_by_name(GLOBAL_PHASE_ATTRIBUTE)
+	.unwrap_or_else(|| "0.0".to_string());
+
+    Ok(Circuit {
+	name: Patch::Value(entry_point.name.clone()),
+	phase,
+	commands: importer.commands,
+	qubits,
+	bits,
+	implicit_permutation,
+	extra: Fields::new(),
+    })
 }
 
 
@@ -235,6 +515,19 @@ pub fn parse_bitcode_file<'ctx>(file_path: &Path, context: &'ctx Context) -> Res
     Module::parse_bitcode_from_path(&file_path, &context).map_err(|err| format!("Parsing {} has failed.", file_path.display()))
 }
 
+/// Parse a QIR file into an `llvm_ir::Module`, accepting both bitcode and
+/// textual IR.
+pub fn parse_qir_file(file_path: &Path) -> Result<llvm_ir::Module, String> {
+    let bytes = std::fs::read(file_path)
+	.map_err(|err| format!("Reading {} has failed: {}", file_path.display(), err))?;
+    // Raw bitcode starts with "BC\xC0\xDE", wrapped bitcode with 0x0B17C0DE.
+    if bytes.starts_with(b"BC\xC0\xDE") || bytes.starts_with(&[0xDE, 0xC0, 0x17, 0x0B]) {
+	llvm_ir::Module::from_bc_path(file_path)
+    } else {
+	llvm_ir::Module::from_ir_path(file_path)
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -326,6 +619,167 @@ mod tests {
     }
     
     
+    #[test]
+    fn test_qir_to_circuit() {
+	let file_path = Path::new("example_files/SimpleGroverBaseProfile.bc");
+	let module = Module::from_bc_path(file_path).expect("File not found.");
+
+	let circ = qir_to_circuit(&module).expect("Import failed.");
+
+	let qubit = |i| circuit::Register("q".to_string(), vec![i]);
+	let bit = |i| circuit::Register("c".to_string(), vec![i]);
+	assert_eq!(circ.qubits, vec![qubit(0), qubit(1), qubit(2)]);
+	assert_eq!(circ.bits, vec![bit(0), bit(1)]);
+	assert_eq!(circ.implicit_permutation.len(), 3);
+	assert_eq!(circ.commands.len(), 32);
+
+	assert_eq!(circ.commands[0].op.op_type, OpType::H);
+	assert_eq!(circ.commands[0].args, vec![qubit(0)]);
+	assert_eq!(circ.commands[6].op.op_type, OpType::Tdg);
+	assert_eq!(circ.commands[8].op.op_type, OpType::CX);
+	assert_eq!(circ.commands[8].args, vec![qubit(2), qubit(0)]);
+
+	let last = circ.commands.last().unwrap();
+	assert_eq!(last.op.op_type, OpType::Measure);
+	assert_eq!(last.args, vec![qubit(1), bit(1)]);
+    }
+
+    #[test]
+    fn test_import_rotations() {
+	let file_path = Path::new("example_files/rotations.ll");
+	let module = Module::from_ir_path(file_path).expect("File not found.");
+
+	let circ = qir_to_circuit(&module).expect("Import failed.");
+
+	let expected = [(OpType::Rx, 0.5), (OpType::Ry, -0.25), (OpType::Rz, 1.0)];
+	for (com, (op_type, angle)) in circ.commands.iter().zip(expected) {
+	    assert_eq!(com.op.op_type, op_type);
+	    let param: f64 = com.op.params.as_ref().unwrap()[0].parse().unwrap();
+	    assert!((param - angle).abs() < 1e-12);
+	}
+	assert_eq!(circ.qubits.len(), 2);
+    }
+
+    #[test]
+    fn test_rotation_round_trip() {
+	let angles = [0.5, -0.25, 1.7, 0.123456789, -3.9];
+	let qubit = circuit::Register("q".to_string(), vec![0]);
+	let commands = angles
+	    .iter()
+	    .zip([OpType::Rx, OpType::Ry, OpType::Rz, OpType::Rz, OpType::Rx])
+	    .map(|(angle, op_type)| circuit::Command {
+		op: circuit::Operation {
+		    op_type,
+		    n_qb: Patch::Missing,
+		    params: Patch::Value(vec![angle.to_string()]),
+		    op_box: Patch::Missing,
+		    signature: Patch::Missing,
+		    conditional: Patch::Missing,
+		    classical: Patch::Missing,
+		    extra: Fields::new(),
+		},
+		args: vec![qubit.clone()],
+		opgroup: Patch::Missing,
+		extra: Fields::new(),
+	    })
+	    .collect();
+	let circ = circuit::Circuit {
+	    name: Patch::Missing,
+	    phase: "0.0".to_string(),
+	    commands,
+	    qubits: vec![qubit.clone()],
+	    bits: vec![],
+	    implicit_permutation: vec![circuit::Permutation(qubit.clone(), qubit)],
+	    extra: Fields::new(),
+	};
+
+	let ir = crate::generate::circuit_to_qir(&circ).expect("Conversion failed.");
+	let file = tempfile::Builder::new()
+	    .suffix(".ll")
+	    .tempfile()
+	    .expect("Creating a file failed.");
+	std::fs::write(file.path(), ir).expect("Writing failed.");
+	let module = Module::from_ir_path(file.path()).expect("Parsing failed.");
+	let func = module
+	    .get_func_by_name("QuantumApplication__Run__body")
+	    .expect("Function not found.");
+	let imported = function_to_circuit(func).expect("Import failed.");
+
+	assert_eq!(imported.commands.len(), angles.len());
+	for ((com, original), angle) in imported.commands.iter().zip(&circ.commands).zip(angles) {
+	    assert_eq!(com.op.op_type, original.op.op_type);
+	    let param: f64 = com.op.params.as_ref().unwrap()[0].parse().unwrap();
+	    assert!((param - angle).abs() < 1e-12);
+	}
+    }
+
+    #[test]
+    fn test_layout_round_trip() {
+	let grid = |i, j| circuit::Register("grid".to_string(), vec![i, j]);
+	let gate = |op_type, args: Vec<circuit::Register>| circuit::Command {
+	    op: circuit::Operation {
+		op_type,
+		n_qb: Patch::Missing,
+		params: Patch::Missing,
+		op_box: Patch::Missing,
+		signature: Patch::Missing,
+		conditional: Patch::Missing,
+		classical: Patch::Missing,
+		extra: Fields::new(),
+	    },
+	    args,
+	    opgroup: Patch::Missing,
+	    extra: Fields::new(),
+	};
+	let qubits = vec![grid(0, 0), grid(0, 1), grid(1, 0), grid(1, 1)];
+	let circ = circuit::Circuit {
+	    name: Patch::Missing,
+	    phase: "0.25".to_string(),
+	    commands: vec![
+		gate(OpType::H, vec![grid(1, 0)]),
+		gate(OpType::CX, vec![grid(1, 0), grid(0, 1)]),
+		// Rx(0.5) up to a phase of 0.25
+		gate(OpType::SX, vec![grid(1, 1)]),
+	    ],
+	    qubits: qubits.clone(),
+	    bits: vec![],
+	    implicit_permutation: qubits
+		.iter()
+		.map(|qb| circuit::Permutation(qb.clone(), qb.clone()))
+		.collect(),
+	    extra: Fields::new(),
+	};
+
+	let ir = crate::generate::circuit_to_qir(&circ).expect("Conversion failed.");
+	let file = tempfile::Builder::new()
+	    .suffix(".ll")
+	    .tempfile()
+	    .expect("Creating a file failed.");
+	std::fs::write(file.path(), ir).expect("Writing failed.");
+	let module = Module::from_ir_path(file.path()).expect("Parsing failed.");
+	let func = module
+	    .get_func_by_name("QuantumApplication__Run__body")
+	    .expect("Function not found.");
+	let layout = RegisterLayout::new(&circ);
+	let imported = function_to_circuit_with_layout(func, &layout).expect("Import failed.");
+
+	assert_eq!(imported.qubits, qubits);
+	assert_eq!(imported.phase, "0.5");
+	assert_eq!(imported.commands.len(), 3);
+	assert_eq!(imported.commands[0].args, vec![grid(1, 0)]);
+	assert_eq!(imported.commands[1].args, vec![grid(1, 0), grid(0, 1)]);
+    }
+
+    #[test]
+    fn test_split_qis_name() {
+	assert_eq!(split_qis_name("__quantum__qis__t__adj"), Some(("t".to_string(), "adj")));
+	assert_eq!(
+	    split_qis_name("Microsoft__Quantum__Intrinsic__H__body"),
+	    Some(("h".to_string(), "body"))
+	);
+	assert_eq!(split_qis_name("__quantum__rt__result_get_one"), None);
+    }
+
     fn parse_simple_instruction() {
 
 	let file_path = Path::new("example_files/SimpleGroverBaseProfile.bc");
@@ -338,7 +792,9 @@ mod tests {
 
 	println!("Instruction {:?}", instructions[1]);
 
-	to_command(&instructions[0]);
+	if let Some(call) = match_call(&instructions[0]) {
+	    Importer::default().to_command(call).expect("Unsupported instruction.");
+	}
 
 	let first_instruction = &first_basicblock.instrs[1];
 	let call = match_call(first_instruction);
@@ -398,15 +854,17 @@ mod tests {
 	let op_args = vec![op_register];
 	let op = circuit::Operation{
 	    op_type: optype,
-	    n_qb: None,
-	    params: None,
-	    op_box: None,
-	    signature: None,
-	    conditional: None
+	    n_qb: Patch::Missing,
+	    params: Patch::Missing,
+	    op_box: Patch::Missing,
+	    signature: Patch::Missing,
+	    conditional: Patch::Missing,
+	    classical: Patch::Missing,
+	    extra: Fields::new(),
 	};
 
 	// Filling out the commands
-	let command = circuit::Command{op: op, args: op_args, opgroup: None};
+	let command = circuit::Command{op: op, args: op_args, opgroup: Patch::Missing, extra: Fields::new()};
 	let commands = vec![command];
 
 	// Defining the global phase and implicit permutation
@@ -415,12 +873,13 @@ mod tests {
 
 	// Creating the circuit with all previously defined parameters
 	let circuit = circuit::Circuit{
-	    name: None,
+	    name: Patch::Missing,
 	    phase: phase,
 	    commands: commands,
 	    qubits: circuit_qubits,
 	    bits: circuit_bits,
-	    implicit_permutation: implicit_permutation
+	    implicit_permutation: implicit_permutation,
+	    extra: Fields::new(),
 	};
 
 
@@ -0,0 +1,955 @@
+//! Synthesis of native gates from the matrices of unitary boxes.
+//!
+//! Matrices are in pytket's convention, with qubit 0 the most significant
+//! bit of the basis index. Angles are returned in half-turns, as gates
+//! elsewhere in the crate, and global phases separately, also in half-turns.
+
+use std::f64::consts::PI;
+
+use num_complex::Complex64;
+
+use crate::circuit::{OpType, Pauli};
+use crate::decompose::Gate;
+use crate::error::CommandError;
+
+/// A square complex matrix, by rows.
+pub type Matrix = Vec<Vec<Complex64>>;
+
+// Box matrices are in double precision, but pytket computes them with
+// rounding errors.
+const TOLERANCE: f64 = 1e-8;
+
+/// Whether `u` is unitary, up to the precision of box matrices.
+pub fn is_unitary(u: &Matrix) -> bool {
+    let n = u.len();
+    u.iter().all(|row| row.len() == n)
+        && (0..n).all(|i| {
+            (0..n).all(|j| {
+                let product: Complex64 = (0..n).map(|k| u[i][k] * u[j][k].conj()).sum();
+                let expected = if i == j { 1.0 } else { 0.0 };
+                (product - expected).norm() < TOLERANCE
+            })
+        })
+}
+
+/// Whether `a` is Hermitian, up to the precision of box matrices.
+pub fn is_hermitian(a: &Matrix) -> bool {
+    let n = a.len();
+    a.iter().all(|row| row.len() == n)
+        && (0..n).all(|i| (0..n).all(|j| (a[i][j] - a[j][i].conj()).norm() < TOLERANCE))
+}
+
+/// Angles `(α, β, γ, δ)` in radians with `u = e^{iα} Rz(β) Ry(γ) Rz(δ)`.
+fn zyz_angles(u: &Matrix) -> (f64, f64, f64, f64) {
+    let det = u[0][0] * u[1][1] - u[0][1] * u[1][0];
+    let alpha = det.arg() / 2.0;
+    // v = e^{-iα} u is in SU(2), with v₁₁ = e^{i(β+δ)/2} cos(γ/2) and
+    // v₁₀ = e^{i(β-δ)/2} sin(γ/2)
+    let phase = Complex64::from_polar(1.0, -alpha);
+    let (v11, v10) = (u[1][1] * phase, u[1][0] * phase);
+    let gamma = 2.0 * v10.norm().atan2(v11.norm());
+    // Either angle is arbitrary when the corresponding entry vanishes
+    let sum = if v11.norm() > f64::EPSILON {
+        v11.arg()
+    } else {
+        0.0
+    };
+    let difference = if v10.norm() > f64::EPSILON {
+        v10.arg()
+    } else {
+        0.0
+    };
+    (alpha, sum + difference, gamma, sum - difference)
+}
+
+/// `Rz`, `Ry`, `Rz` gates on `qubit` equal to `u` up to global phase.
+fn zyz_gates(u: &Matrix, qubit: usize) -> Vec<Gate> {
+    let (_, beta, gamma, delta) = zyz_angles(u);
+    vec![
+        Gate::new(OpType::Rz, vec![delta / PI], vec![qubit]),
+        Gate::new(OpType::Ry, vec![gamma / PI], vec![qubit]),
+        Gate::new(OpType::Rz, vec![beta / PI], vec![qubit]),
+    ]
+}
+
+/// Decompose a single-qubit unitary into `Rz`, `Ry`, `Rz` on qubit 0.
+/// Returns the gates and the global phase.
+pub fn unitary1q(u: &Matrix) -> Result<(Vec<Gate>, f64), CommandError> {
+    if u.len() != 2 || !is_unitary(u) {
+        return Err(CommandError::NotUnitary(OpType::Unitary1qBox));
+    }
+    let (alpha, ..) = zyz_angles(u);
+    Ok((zyz_gates(u, 0), alpha / PI))
+}
+
+/// Decompose a two-qubit unitary into at most three `CX` and single-qubit
+/// rotations, using the KAK decomposition `u = (A₁⊗B₁) N(a, b, c) (A₂⊗B₂)`
+/// with `N(a, b, c) = exp(i(a XX + b YY + c ZZ))`. Returns the gates and the
+/// global phase.
+pub fn unitary2q(u: &Matrix) -> Result<(Vec<Gate>, f64), CommandError> {
+    if u.len() != 4 || !is_unitary(u) {
+        return Err(CommandError::NotUnitary(OpType::Unitary2qBox));
+    }
+    let magic = magic_basis();
+    let magic_adjoint = adjoint(&magic);
+
+    // In the magic basis, local unitaries of SU(2)⊗SU(2) are real orthogonal
+    // and N(a, b, c) is diagonal.
+    let root = determinant(u).powf(0.25);
+    let normalised = scale(u, root.inv());
+    let up = multiply(&multiply(&magic_adjoint, &normalised), &magic);
+    // up = K₁ F K₂ with K₁, K₂ real orthogonal and F diagonal, so that
+    // upᵀ up = K₂ᵀ F² K₂ is diagonalised by a real orthogonal matrix.
+    let mut k2t = diagonalise_symmetric_unitary(&multiply(&transpose(&up), &up));
+    if determinant(&k2t).re < 0.0 {
+        for row in k2t.iter_mut() {
+            row[0] = -row[0];
+        }
+    }
+    let squares = multiply(
+        &multiply(&transpose(&k2t), &transpose(&up)),
+        &multiply(&up, &k2t),
+    );
+    let mut f: Vec<Complex64> = (0..4)
+        .map(|i| Complex64::from_polar(1.0, squares[i][i].arg() / 2.0))
+        .collect();
+    // Choose the square roots with det(F) = 1, so that K₁ is in SO(4) as well
+    if f.iter().product::<Complex64>().re < 0.0 {
+        f[0] = -f[0];
+    }
+    let f_inverse = diagonal(&f.iter().map(|x| x.inv()).collect::<Vec<_>>());
+    let k1 = multiply(&multiply(&up, &k2t), &f_inverse);
+
+    let local = |k: &Matrix| multiply(&multiply(&magic, k), &magic_adjoint);
+    let (a1, b1) = tensor_factors(&local(&k1));
+    let (a2, b2) = tensor_factors(&local(&transpose(&k2t)));
+
+    let mut gates = zyz_gates(&a2, 0);
+    gates.extend(zyz_gates(&b2, 1));
+    // F is a global phase when all its entries agree
+    if f.iter().any(|x| (x - f[0]).norm() > 1e-9) {
+        let [a, b, c] = interaction_coefficients(&magic, &f);
+        gates.extend(interaction_gates(a, b, c));
+    }
+    gates.extend(zyz_gates(&a1, 0));
+    gates.extend(zyz_gates(&b1, 1));
+
+    let synthesised = gates_unitary(&gates, 2);
+    let overlap: Complex64 = synthesised
+        .iter()
+        .flatten()
+        .zip(u.iter().flatten())
+        .map(|(v, u)| v.conj() * u)
+        .sum();
+    Ok((gates, overlap.arg() / PI))
+}
+
+/// Decompose the unitary `e^{itA}` of an `ExpBox`, with `A` a Hermitian
+/// matrix on one or two qubits. Returns the gates and the global phase.
+pub fn exp_box(a: &Matrix, t: f64) -> Result<(Vec<Gate>, f64), CommandError> {
+    if !is_hermitian(a) {
+        return Err(CommandError::NotHermitian(OpType::ExpBox));
+    }
+    let u = exponential(&scale(a, Complex64::new(0.0, t)));
+    match u.len() {
+        2 => unitary1q(&u),
+        4 => unitary2q(&u),
+        _ => Err(CommandError::UnsupportedOp(OpType::ExpBox)),
+    }
+}
+
+/// Gates for the Pauli exponential `exp(-iπt/2 P)`, with `P` the tensor
+/// product of `paulis` and `t` in half-turns: a change of basis to Z on each
+/// qubit, a `CX` ladder computing the parity onto the last qubit, an `Rz` and
+/// the inverse of the ladder and basis change. Returns the gates and the
+/// global phase, which is only non-zero for the identity.
+pub fn pauli_exp(paulis: &[Pauli], t: f64) -> (Vec<Gate>, f64) {
+    let active: Vec<(usize, Pauli)> = paulis
+        .iter()
+        .enumerate()
+        .filter(|(_, &pauli)| pauli != Pauli::I)
+        .map(|(qubit, &pauli)| (qubit, pauli))
+        .collect();
+    let last = match active.last() {
+        Some(&(qubit, _)) => qubit,
+        None => return (vec![], -t / 2.0),
+    };
+
+    // Gates with V†ZV = P, and their inverses
+    let basis_change = |inverse: bool| {
+        let sign = if inverse { -1.0 } else { 1.0 };
+        active
+            .iter()
+            .filter_map(move |&(qubit, pauli)| match pauli {
+                Pauli::X => Some(Gate::new(OpType::H, vec![], vec![qubit])),
+                Pauli::Y => Some(Gate::new(OpType::Rx, vec![sign * 0.5], vec![qubit])),
+                _ => None,
+            })
+    };
+    let ladder: Vec<Gate> = active
+        .windows(2)
+        .map(|pair| Gate::new(OpType::CX, vec![], vec![pair[0].0, pair[1].0]))
+        .collect();
+
+    let mut gates: Vec<Gate> = basis_change(false).collect();
+    gates.extend(ladder.iter().cloned());
+    gates.push(Gate::new(OpType::Rz, vec![t], vec![last]));
+    gates.extend(ladder.into_iter().rev());
+    gates.extend(basis_change(true));
+    (gates, 0.0)
+}
+
+/// Gates for a phase polynomial box on `n_qubits` qubits: each term `(p, θ)`
+/// applies `Rz(θ)` to the parity `p` of the inputs, and the outputs are the
+/// parities given by the rows of `linear_transformation`. Returns the gates
+/// and the global phase.
+///
+/// The parities are computed by the GraySynth algorithm of Amy, Azimzadeh and
+/// Mosca, "On the CNOT-complexity of CNOT-phase circuits", and the linear
+/// transformation is then fixed by Gaussian elimination.
+pub fn phase_poly(
+    n_qubits: usize,
+    phase_polynomial: &[(Vec<bool>, f64)],
+    linear_transformation: &[Vec<bool>],
+) -> Result<(Vec<Gate>, f64), CommandError> {
+    let invalid = || CommandError::NotUnitary(OpType::PhasePolyBox);
+    if linear_transformation.len() != n_qubits
+        || linear_transformation
+            .iter()
+            .any(|row| row.len() != n_qubits)
+        || phase_polynomial
+            .iter()
+            .any(|(parity, _)| parity.len() != n_qubits)
+    {
+        return Err(invalid());
+    }
+
+    let mut gates = vec![];
+    let mut phase = 0.0;
+    // Rows of the parities held by each qubit, in terms of the inputs
+    let mut held: Vec<Vec<bool>> = identity_rows(n_qubits);
+    // Terms still to apply, with parities in terms of the qubits' current values
+    let mut terms: Vec<(Vec<bool>, f64)> = vec![];
+    for (parity, angle) in phase_polynomial {
+        if parity.iter().any(|&bit| bit) {
+            terms.push((parity.clone(), *angle));
+        } else {
+            // Rz on a constant parity is a global phase
+            phase -= angle / 2.0;
+        }
+    }
+
+    // Each entry is a set of terms, the qubits not yet split on, and the
+    // qubit every term has in its parity, if any.
+    let mut stack: Vec<(Vec<usize>, Vec<usize>, Option<usize>)> =
+        vec![((0..terms.len()).collect(), (0..n_qubits).collect(), None)];
+    while let Some((mut set, remaining, target)) = stack.pop() {
+        if let Some(i) = target {
+            // Clear the other qubits every term depends on
+            while let Some(j) = (0..n_qubits)
+                .find(|&j| j != i && !set.is_empty() && set.iter().all(|&t| terms[t].0[j]))
+            {
+                // CX(j, i) makes qubit i hold its parity xor that of qubit j, so
+                // parities over the new values depend on j iff they depended
+                // on exactly one of i and j
+                gates.push(Gate::new(OpType::CX, vec![], vec![j, i]));
+                for term in terms.iter_mut() {
+                    term.0[j] ^= term.0[i];
+                }
+                let row_j = held[j].clone();
+                for (bit, other) in held[i].iter_mut().zip(row_j) {
+                    *bit ^= other;
+                }
+            }
+            // Terms on qubit i alone are ready
+            set.retain(|&t| {
+                let ready = (0..n_qubits).all(|k| terms[t].0[k] == (k == i));
+                if ready {
+                    gates.push(Gate::new(OpType::Rz, vec![terms[t].1], vec![i]));
+                }
+                !ready
+            });
+        }
+        if set.is_empty() || remaining.is_empty() {
+            continue;
+        }
+        // Split on the qubit which most terms agree on
+        let count = |j: usize| set.iter().filter(|&&t| terms[t].0[j]).count();
+        let &j = remaining
+            .iter()
+            .max_by_key(|&&j| std::cmp::max(count(j), set.len() - count(j)))
+            .unwrap();
+        let remaining: Vec<usize> = remaining.into_iter().filter(|&k| k != j).collect();
+        let (with, without): (Vec<usize>, Vec<usize>) = set.iter().partition(|&&t| terms[t].0[j]);
+        stack.push((without, remaining.clone(), target));
+        stack.push((with, remaining, target.or(Some(j))));
+    }
+
+    // Return to the inputs, then apply the linear transformation
+    let to_inputs = gaussian_elimination(held).ok_or_else(invalid)?;
+    let from_inputs = gaussian_elimination(linear_transformation.to_vec()).ok_or_else(invalid)?;
+    gates.extend(to_inputs);
+    gates.extend(from_inputs.into_iter().rev());
+    Ok((gates, phase))
+}
+
+fn identity_rows(n: usize) -> Vec<Vec<bool>> {
+    (0..n).map(|i| (0..n).map(|j| i == j).collect()).collect()
+}
+
+/// `CX` gates reducing the qubits holding the parities `rows` of the inputs
+/// to the inputs themselves, or `None` if the rows are not independent.
+fn gaussian_elimination(mut rows: Vec<Vec<bool>>) -> Option<Vec<Gate>> {
+    let n = rows.len();
+    let mut gates = vec![];
+    // CX(control, target) adds the control's row to the target's
+    let mut cx = |rows: &mut Vec<Vec<bool>>, control: usize, target: usize| {
+        gates.push(Gate::new(OpType::CX, vec![], vec![control, target]));
+        let row = rows[control].clone();
+        for (bit, other) in rows[target].iter_mut().zip(row) {
+            *bit ^= other;
+        }
+    };
+    for col in 0..n {
+        let pivot = (col..n).find(|&r| rows[r][col])?;
+        if pivot != col {
+            cx(&mut rows, pivot, col);
+        }
+        for row in 0..n {
+            if row != col && rows[row][col] {
+                cx(&mut rows, col, row);
+            }
+        }
+    }
+    Some(gates)
+}
+
+/// Columns of the magic basis, in which `SU(2)⊗SU(2)` is `SO(4)`.
+fn magic_basis() -> Matrix {
+    let r = 0.5_f64.sqrt();
+    let (zero, one, i) = (
+        Complex64::new(0.0, 0.0),
+        Complex64::new(r, 0.0),
+        Complex64::new(0.0, r),
+    );
+    vec![
+        vec![one, i, zero, zero],
+        vec![zero, zero, i, one],
+        vec![zero, zero, i, -one],
+        vec![one, -i, zero, zero],
+    ]
+}
+
+/// Coefficients `(a, b, c)` of `N(a, b, c)`, with eigenvalues `f` on the
+/// magic basis up to a global phase.
+fn interaction_coefficients(magic: &Matrix, f: &[Complex64]) -> [f64; 3] {
+    let (x, y, z) = (
+        pauli_matrix(OpType::X),
+        pauli_matrix(OpType::Y),
+        pauli_matrix(OpType::Z),
+    );
+    let angles: Vec<f64> = f.iter().map(|entry| entry.arg()).collect();
+    [kron(&x, &x), kron(&y, &y), kron(&z, &z)].map(|pp| {
+        // The eigenvalues ±1 of XX, YY, ZZ and the identity on the magic
+        // basis form orthogonal vectors, so the coefficients are projections.
+        let eigenvalues = multiply(&multiply(&adjoint(magic), &pp), magic);
+        (0..4)
+            .map(|k| eigenvalues[k][k].re * angles[k])
+            .sum::<f64>()
+            / 4.0
+    })
+}
+
+/// Three `CX` circuit for `N(a, b, c)` up to global phase, after Vatan and
+/// Williams, "Optimal quantum circuits for general two-qubit gates".
+fn interaction_gates(a: f64, b: f64, c: f64) -> Vec<Gate> {
+    let cx = |control, target| Gate::new(OpType::CX, vec![], vec![control, target]);
+    vec![
+        Gate::new(OpType::Rz, vec![0.5], vec![1]),
+        cx(1, 0),
+        Gate::new(OpType::Rz, vec![-2.0 * c / PI - 0.5], vec![0]),
+        Gate::new(OpType::Ry, vec![-2.0 * a / PI - 0.5], vec![1]),
+        cx(0, 1),
+        Gate::new(OpType::Ry, vec![2.0 * b / PI + 0.5], vec![1]),
+        cx(1, 0),
+        Gate::new(OpType::Rz, vec![-0.5], vec![0]),
+    ]
+}
+
+/// Real orthogonal matrix whose columns are eigenvectors of the symmetric
+/// unitary `s`. Its real and imaginary parts are commuting real symmetric
+/// matrices, diagonalised together through a generic linear combination.
+fn diagonalise_symmetric_unitary(s: &Matrix) -> Matrix {
+    let n = s.len();
+    let mut best = (f64::INFINITY, vec![]);
+    for weight in [0.618_033_988_7, 1.324_717_957_2, 0.367_879_441_1] {
+        let combination: Vec<Vec<f64>> = (0..n)
+            .map(|i| {
+                (0..n)
+                    .map(|j| {
+                        let x = (s[i][j] + s[j][i]) / 2.0;
+                        x.re + weight * x.im
+                    })
+                    .collect()
+            })
+            .collect();
+        let vectors = real_matrix(&symmetric_eigenvectors(combination));
+        let d = multiply(&multiply(&transpose(&vectors), s), &vectors);
+        let off_diagonal = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| d[i][j].norm())
+            .fold(0.0, f64::max);
+        if off_diagonal < best.0 {
+            best = (off_diagonal, vectors);
+        }
+        // Eigenvalues of the combination only coincide by accident
+        if off_diagonal < 1e-9 {
+            break;
+        }
+    }
+    best.1
+}
+
+/// Eigenvectors, as columns, of a real symmetric matrix by cyclic Jacobi
+/// rotations.
+pub(crate) fn symmetric_eigenvectors(mut a: Vec<Vec<f64>>) -> Vec<Vec<f64>> {
+    let n = a.len();
+    let mut v: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    for _ in 0..100 {
+        let off: f64 = (0..n)
+            .flat_map(|i| (0..n).filter(move |&j| j != i).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off < 1e-30 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let cos = 1.0 / (t * t + 1.0).sqrt();
+                let sin = t * cos;
+                for row in a.iter_mut() {
+                    let (akp, akq) = (row[p], row[q]);
+                    row[p] = cos * akp - sin * akq;
+                    row[q] = sin * akp + cos * akq;
+                }
+                let (head, tail) = a.split_at_mut(q);
+                for (apk, aqk) in head[p].iter_mut().zip(tail[0].iter_mut()) {
+                    let (x, y) = (*apk, *aqk);
+                    *apk = cos * x - sin * y;
+                    *aqk = sin * x + cos * y;
+                }
+                for row in v.iter_mut() {
+                    let (vkp, vkq) = (row[p], row[q]);
+                    row[p] = cos * vkp - sin * vkq;
+                    row[q] = sin * vkp + cos * vkq;
+                }
+            }
+        }
+    }
+    v
+}
+
+/// Factors `(a, b)` of `k = a⊗b`, each up to a phase.
+fn tensor_factors(k: &Matrix) -> (Matrix, Matrix) {
+    // Each 2x2 block of entries k[2i + r][2j + s] over (i, j) is a multiple
+    // of a; take the largest one.
+    let block = |r: usize, s: usize| -> Matrix {
+        (0..2)
+            .map(|i| (0..2).map(|j| k[2 * i + r][2 * j + s]).collect())
+            .collect()
+    };
+    let norm = |m: &Matrix| m.iter().flatten().map(|x| x.norm_sqr()).sum::<f64>();
+    let (r, s) = [(0, 0), (0, 1), (1, 0), (1, 1)]
+        .into_iter()
+        .max_by(|&(r1, s1), &(r2, s2)| {
+            norm(&block(r1, s1))
+                .partial_cmp(&norm(&block(r2, s2)))
+                .unwrap()
+        })
+        .unwrap();
+    let a = block(r, s);
+    let a = scale(&a, determinant(&a).sqrt().inv());
+    let (i, j) = [(0, 0), (0, 1), (1, 0), (1, 1)]
+        .into_iter()
+        .max_by(|&(i1, j1), &(i2, j2)| a[i1][j1].norm().partial_cmp(&a[i2][j2].norm()).unwrap())
+        .unwrap();
+    let b = (0..2)
+        .map(|r| (0..2).map(|s| k[2 * i + r][2 * j + s] / a[i][j]).collect())
+        .collect();
+    (a, b)
+}
+
+/// Unitary of gates acting on `n_qubits` qubits, for the gates synthesis
+/// produces: rotations, Paulis, `H` and `CX`.
+pub(crate) fn gates_unitary(gates: &[Gate], n_qubits: usize) -> Matrix {
+    let dim = 1 << n_qubits;
+    let mut u = diagonal(&vec![Complex64::new(1.0, 0.0); dim]);
+    // Qubit 0 is the most significant bit
+    let bit = |qubit: usize| 1 << (n_qubits - 1 - qubit);
+    for gate in gates {
+        let matrix = match &gate.op_type {
+            OpType::CX => {
+                let (control, target) = (bit(gate.args[0]), bit(gate.args[1]));
+                (0..dim)
+                    .map(|i| {
+                        let j = if i & control != 0 { i ^ target } else { i };
+                        (0..dim)
+                            .map(|k| Complex64::new(if k == j { 1.0 } else { 0.0 }, 0.0))
+                            .collect()
+                    })
+                    .collect()
+            }
+            op_type => {
+                let single = single_qubit_matrix(op_type, &gate.params);
+                let b = bit(gate.args[0]);
+                (0..dim)
+                    .map(|i| {
+                        (0..dim)
+                            .map(|j| {
+                                if i & !b == j & !b {
+                                    single[usize::from(i & b != 0)][usize::from(j & b != 0)]
+                                } else {
+                                    Complex64::new(0.0, 0.0)
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect()
+            }
+        };
+        u = multiply(&matrix, &u);
+    }
+    u
+}
+
+fn single_qubit_matrix(op_type: &OpType, params: &[f64]) -> Matrix {
+    let c = Complex64::new;
+    let (cos, sin) = match params {
+        [angle] => ((angle * PI / 2.0).cos(), (angle * PI / 2.0).sin()),
+        _ => (0.0, 0.0),
+    };
+    match op_type {
+        OpType::Rz => diagonal(&[c(cos, -sin), c(cos, sin)]),
+        OpType::Ry => vec![
+            vec![c(cos, 0.0), c(-sin, 0.0)],
+            vec![c(sin, 0.0), c(cos, 0.0)],
+        ],
+        OpType::Rx => vec![
+            vec![c(cos, 0.0), c(0.0, -sin)],
+            vec![c(0.0, -sin), c(cos, 0.0)],
+        ],
+        OpType::H => {
+            let r = c(0.5_f64.sqrt(), 0.0);
+            vec![vec![r, r], vec![r, -r]]
+        }
+        OpType::X | OpType::Y | OpType::Z => pauli_matrix(op_type.clone()),
+        OpType::S => diagonal(&[c(1.0, 0.0), c(0.0, 1.0)]),
+        OpType::Sdg => diagonal(&[c(1.0, 0.0), c(0.0, -1.0)]),
+        _ => unreachable!("{:?} is not produced by synthesis", op_type),
+    }
+}
+
+fn pauli_matrix(op_type: OpType) -> Matrix {
+    let c = Complex64::new;
+    match op_type {
+        OpType::X => vec![
+            vec![c(0.0, 0.0), c(1.0, 0.0)],
+            vec![c(1.0, 0.0), c(0.0, 0.0)],
+        ],
+        OpType::Y => vec![
+            vec![c(0.0, 0.0), c(0.0, -1.0)],
+            vec![c(0.0, 1.0), c(0.0, 0.0)],
+        ],
+        _ => diagonal(&[c(1.0, 0.0), c(-1.0, 0.0)]),
+    }
+}
+
+pub(crate) fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| (0..n).map(|k| a[i][k] * b[k][j]).sum())
+                .collect()
+        })
+        .collect()
+}
+
+fn adjoint(a: &Matrix) -> Matrix {
+    let n = a.len();
+    (0..n)
+        .map(|i| (0..n).map(|j| a[j][i].conj()).collect())
+        .collect()
+}
+
+fn transpose(a: &Matrix) -> Matrix {
+    let n = a.len();
+    (0..n).map(|i| (0..n).map(|j| a[j][i]).collect()).collect()
+}
+
+fn kron(a: &Matrix, b: &Matrix) -> Matrix {
+    let (n, m) = (a.len(), b.len());
+    (0..n * m)
+        .map(|i| {
+            (0..n * m)
+                .map(|j| a[i / m][j / m] * b[i % m][j % m])
+                .collect()
+        })
+        .collect()
+}
+
+fn scale(a: &Matrix, factor: Complex64) -> Matrix {
+    a.iter()
+        .map(|row| row.iter().map(|x| x * factor).collect())
+        .collect()
+}
+
+fn diagonal(entries: &[Complex64]) -> Matrix {
+    let n = entries.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    if i == j {
+                        entries[i]
+                    } else {
+                        Complex64::new(0.0, 0.0)
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn real_matrix(a: &[Vec<f64>]) -> Matrix {
+    a.iter()
+        .map(|row| row.iter().map(|&x| Complex64::new(x, 0.0)).collect())
+        .collect()
+}
+
+/// Matrix exponential by scaling and squaring: `e^a = (e^{a/2^s})^{2^s}`
+/// with `s` such that `a/2^s` has a norm of at most 1/2, where a Taylor series of
+/// 20 terms is exact to double precision.
+fn exponential(a: &Matrix) -> Matrix {
+    let norm = a.iter().flatten().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+    let squarings = (2.0 * norm).log2().ceil().max(0.0) as i32;
+    let scaled = scale(a, Complex64::new(0.5_f64.powi(squarings), 0.0));
+    let mut term = diagonal(&vec![Complex64::new(1.0, 0.0); a.len()]);
+    let mut sum = term.clone();
+    for k in 1..=20 {
+        term = scale(
+            &multiply(&term, &scaled),
+            Complex64::new(1.0 / k as f64, 0.0),
+        );
+        for (row, term_row) in sum.iter_mut().zip(&term) {
+            for (x, y) in row.iter_mut().zip(term_row) {
+                *x += y;
+            }
+        }
+    }
+    for _ in 0..squarings {
+        sum = multiply(&sum, &sum);
+    }
+    sum
+}
+
+/// Determinant by Gaussian elimination with partial pivoting.
+fn determinant(a: &Matrix) -> Complex64 {
+    let n = a.len();
+    let mut a = a.clone();
+    let mut det = Complex64::new(1.0, 0.0);
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i][col].norm().partial_cmp(&a[j][col].norm()).unwrap())
+            .unwrap();
+        if a[pivot][col].norm() == 0.0 {
+            return Complex64::new(0.0, 0.0);
+        }
+        if pivot != col {
+            a.swap(pivot, col);
+            det = -det;
+        }
+        det *= a[col][col];
+        let (top, bottom) = a.split_at_mut(col + 1);
+        let pivot_row = &top[col];
+        for row in bottom {
+            let factor = row[col] / pivot_row[col];
+            for (x, y) in row.iter_mut().zip(pivot_row).skip(col) {
+                *x -= factor * y;
+            }
+        }
+    }
+    det
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(re: f64, im: f64) -> Complex64 {
+        Complex64::new(re, im)
+    }
+
+    fn gate(op_type: OpType, params: Vec<f64>, args: Vec<usize>) -> Gate {
+        Gate::new(op_type, params, args)
+    }
+
+    /// Check that the gates and phase synthesised for `u` reproduce it.
+    fn check_synthesis(u: &Matrix, (gates, phase): (Vec<Gate>, f64)) {
+        let n_qubits = u.len().trailing_zeros() as usize;
+        let synthesised = scale(
+            &gates_unitary(&gates, n_qubits),
+            Complex64::from_polar(1.0, phase * PI),
+        );
+        for (row_u, row_v) in u.iter().zip(&synthesised) {
+            for (x, y) in row_u.iter().zip(row_v) {
+                assert!((x - y).norm() < 1e-9, "{:?} != {:?}", u, synthesised);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unitary1q() {
+        let r = 0.5_f64.sqrt();
+        // H
+        let u = vec![vec![c(r, 0.0), c(r, 0.0)], vec![c(r, 0.0), c(-r, 0.0)]];
+        check_synthesis(&u, unitary1q(&u).unwrap());
+        // X, with a vanishing diagonal
+        let u = pauli_matrix(OpType::X);
+        check_synthesis(&u, unitary1q(&u).unwrap());
+        // S with a phase, with a vanishing off-diagonal
+        let u = diagonal(&[c(0.0, 1.0), c(-1.0, 0.0)]);
+        check_synthesis(&u, unitary1q(&u).unwrap());
+        // A generic unitary, e^{0.3i} Rz(0.4) Ry(1.1) Rz(-2.3)
+        let gates = [
+            gate(OpType::Rz, vec![-2.3 / PI], vec![0]),
+            gate(OpType::Ry, vec![1.1 / PI], vec![0]),
+            gate(OpType::Rz, vec![0.4 / PI], vec![0]),
+        ];
+        let u = scale(&gates_unitary(&gates, 1), Complex64::from_polar(1.0, 0.3));
+        check_synthesis(&u, unitary1q(&u).unwrap());
+    }
+
+    #[test]
+    fn test_unitary2q() {
+        let cx = |control, target| gate(OpType::CX, vec![], vec![control, target]);
+        let rotations = |angles: [f64; 6]| {
+            vec![
+                gate(OpType::Rz, vec![angles[0]], vec![0]),
+                gate(OpType::Ry, vec![angles[1]], vec![0]),
+                gate(OpType::Rx, vec![angles[2]], vec![0]),
+                gate(OpType::Rx, vec![angles[3]], vec![1]),
+                gate(OpType::Rz, vec![angles[4]], vec![1]),
+                gate(OpType::Ry, vec![angles[5]], vec![1]),
+            ]
+        };
+        let circuits = vec![
+            // Local unitaries only
+            rotations([0.1, 0.2, 0.3, 0.4, 0.5, 0.6]),
+            vec![cx(0, 1)],
+            vec![cx(1, 0)],
+            // SWAP
+            vec![cx(0, 1), cx(1, 0), cx(0, 1)],
+            vec![gate(OpType::H, vec![], vec![0]), cx(0, 1)],
+            // Generic
+            [
+                rotations([0.3, -1.2, 0.7, 0.05, 1.9, -0.4]),
+                vec![cx(0, 1)],
+                rotations([1.3, 0.2, -0.9, 0.6, -1.1, 0.8]),
+                vec![cx(1, 0)],
+                rotations([-0.7, 0.45, 0.15, 1.2, 0.35, -1.6]),
+                vec![cx(0, 1)],
+                rotations([0.25, 0.9, -0.3, -0.8, 0.1, 1.4]),
+            ]
+            .concat(),
+        ];
+        for gates in circuits {
+            let u = scale(&gates_unitary(&gates, 2), Complex64::from_polar(1.0, 0.7));
+            let synthesis = unitary2q(&u).expect("Synthesis failed.");
+            let n_cx = synthesis
+                .0
+                .iter()
+                .filter(|g| g.op_type == OpType::CX)
+                .count();
+            assert!(n_cx <= 3);
+            check_synthesis(&u, synthesis);
+        }
+
+        // Local unitaries need no CX
+        let u = gates_unitary(&rotations([0.1, 0.2, 0.3, 0.4, 0.5, 0.6]), 2);
+        let (gates, _) = unitary2q(&u).unwrap();
+        assert!(gates.iter().all(|g| g.op_type != OpType::CX));
+    }
+
+    #[test]
+    fn test_pauli_exp() {
+        use Pauli::*;
+        for paulis in [
+            vec![X],
+            vec![Y, Z],
+            vec![X, I, Y],
+            vec![Z, Y, I, X],
+            vec![I, I],
+        ] {
+            let t = 0.37;
+            let n_qubits = paulis.len();
+            let p = paulis
+                .iter()
+                .map(|&pauli| match pauli {
+                    I => diagonal(&[c(1.0, 0.0), c(1.0, 0.0)]),
+                    X => pauli_matrix(OpType::X),
+                    Y => pauli_matrix(OpType::Y),
+                    Z => pauli_matrix(OpType::Z),
+                })
+                .reduce(|a, b| kron(&a, &b))
+                .unwrap();
+            let angle = t * PI / 2.0;
+            let u: Matrix = (0..1 << n_qubits)
+                .map(|i| {
+                    (0..1 << n_qubits)
+                        .map(|j| {
+                            let identity = if i == j { angle.cos() } else { 0.0 };
+                            c(identity, 0.0) - c(0.0, angle.sin()) * p[i][j]
+                        })
+                        .collect()
+                })
+                .collect();
+            check_synthesis(&u, pauli_exp(&paulis, t));
+        }
+    }
+
+    #[test]
+    fn test_phase_poly() {
+        let bits = |s: &str| s.chars().map(|c| c == '1').collect::<Vec<_>>();
+        let cases = vec![
+            (vec![("1", 0.3)], vec!["1"]),
+            (
+                vec![("110", 0.3), ("011", -0.7), ("111", 1.1), ("100", 0.25)],
+                vec!["100", "010", "001"],
+            ),
+            (
+                vec![
+                    ("1011", 0.4),
+                    ("0110", 0.9),
+                    ("1111", -0.2),
+                    ("0001", 0.6),
+                    ("0000", 0.5),
+                ],
+                vec!["1100", "0100", "0011", "1001"],
+            ),
+            // Linear transformation only
+            (vec![], vec!["01", "11"]),
+        ];
+        for (polynomial, linear) in cases {
+            let n_qubits = linear.len();
+            let polynomial: Vec<_> = polynomial
+                .into_iter()
+                .map(|(parity, angle)| (bits(parity), angle))
+                .collect();
+            let linear: Vec<_> = linear.into_iter().map(bits).collect();
+            // |x⟩ ↦ exp(-iπ/2 Σ θ (-1)^{p·x}) |Ax⟩, qubit 0 the most significant bit
+            let dim = 1 << n_qubits;
+            let bit = |x: usize, k: usize| (x >> (n_qubits - 1 - k)) & 1 == 1;
+            let parity =
+                |p: &[bool], x: usize| (0..n_qubits).filter(|&k| p[k] && bit(x, k)).count() % 2;
+            let images = (0..dim).map(|x| {
+                let angle: f64 = polynomial
+                    .iter()
+                    .map(|(p, theta)| {
+                        let sign = if parity(p, x) == 1 { -1.0 } else { 1.0 };
+                        -PI / 2.0 * theta * sign
+                    })
+                    .sum();
+                let y = (0..n_qubits).fold(0, |y, k| (y << 1) | parity(&linear[k], x));
+                (y, Complex64::from_polar(1.0, angle))
+            });
+            let mut u = vec![vec![c(0.0, 0.0); dim]; dim];
+            for (x, (y, entry)) in images.enumerate() {
+                u[y][x] = entry;
+            }
+            let synthesis = phase_poly(n_qubits, &polynomial, &linear).expect("Synthesis failed.");
+            check_synthesis(&u, synthesis);
+        }
+
+        assert_eq!(
+            phase_poly(2, &[], &[bits("11"), bits("11")]).unwrap_err(),
+            CommandError::NotUnitary(OpType::PhasePolyBox)
+        );
+    }
+
+    #[test]
+    fn test_exp_box() {
+        // e^{itA} = cos(t) I + i sin(t) A when A² = I
+        let t: f64 = 0.7;
+        let a = kron(&pauli_matrix(OpType::X), &pauli_matrix(OpType::Y));
+        let expected: Matrix = (0..4)
+            .map(|i| {
+                (0..4)
+                    .map(|j| {
+                        let identity = if i == j { t.cos() } else { 0.0 };
+                        c(identity, 0.0) + c(0.0, t.sin()) * a[i][j]
+                    })
+                    .collect()
+            })
+            .collect();
+        check_synthesis(&expected, exp_box(&a, t).unwrap());
+
+        // e^{itVDV†} = V e^{itD} V† for diagonal D, with entries large
+        // enough to need squaring
+        let v = gates_unitary(
+            &[
+                gate(OpType::Ry, vec![0.3], vec![0]),
+                gate(OpType::CX, vec![], vec![0, 1]),
+                gate(OpType::Rx, vec![1.2], vec![1]),
+            ],
+            2,
+        );
+        let d = [3.0, -1.2, 0.4, 7.5];
+        let a = multiply(
+            &multiply(&v, &diagonal(&d.map(|x| c(x, 0.0)))),
+            &adjoint(&v),
+        );
+        let expected = multiply(
+            &multiply(&v, &diagonal(&d.map(|x| Complex64::from_polar(1.0, t * x)))),
+            &adjoint(&v),
+        );
+        check_synthesis(&expected, exp_box(&a, t).unwrap());
+
+        let u = vec![
+            vec![c(0.0, 0.0), c(1.0, 0.0)],
+            vec![c(0.0, 0.0), c(0.0, 0.0)],
+        ];
+        assert_eq!(
+            exp_box(&u, t).unwrap_err(),
+            CommandError::NotHermitian(OpType::ExpBox)
+        );
+    }
+
+    #[test]
+    fn test_not_unitary() {
+        let u = vec![
+            vec![c(1.0, 0.0), c(1.0, 0.0)],
+            vec![c(0.0, 0.0), c(1.0, 0.0)],
+        ];
+        assert_eq!(
+            unitary1q(&u).unwrap_err(),
+            CommandError::NotUnitary(OpType::Unitary1qBox)
+        );
+        let u = vec![
+            vec![c(0.0, 0.0), c(0.0, 1.0)],
+            vec![c(0.0, 1.0), c(0.0, 0.0)],
+        ];
+        assert!(unitary1q(&u).is_ok());
+        assert_eq!(
+            unitary2q(&u).unwrap_err(),
+            CommandError::NotUnitary(OpType::Unitary2qBox)
+        );
+    }
+}
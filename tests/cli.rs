@@ -0,0 +1,133 @@
+use std::fs;
+use std::path::PathBuf;
+
+use assert_cmd::Command;
+
+fn tket_qir() -> Command {
+    Command::cargo_bin("tket-qir").expect("Binary not found.")
+}
+
+fn example(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("example_files")
+        .join(name)
+}
+
+fn stdout(command: &mut Command) -> String {
+    let output = command.output().expect("Running failed.");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("Output is not UTF-8.")
+}
+
+fn stderr(command: &mut Command) -> String {
+    let output = command.output().expect("Running failed.");
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr).expect("Output is not UTF-8.")
+}
+
+#[test]
+fn test_format_from_extension() {
+    let dir = tempfile::tempdir().expect("Creating a directory failed.");
+    let circuit = example("simple_H_pytket_circuit.json");
+
+    let bc = dir.path().join("out.bc");
+    tket_qir()
+        .arg("to-qir")
+        .arg(&circuit)
+        .arg("-o")
+        .arg(&bc)
+        .assert()
+        .success();
+    assert!(fs::read(&bc)
+        .expect("Reading failed.")
+        .starts_with(b"BC\xC0\xDE"));
+
+    let ll = dir.path().join("out.ll");
+    tket_qir()
+        .arg("to-qir")
+        .arg(&circuit)
+        .arg("-o")
+        .arg(&ll)
+        .assert()
+        .success();
+    let ir = fs::read_to_string(&ll).expect("Reading failed.");
+    assert!(ir.contains("__quantum__qis__h__body"));
+
+    // --emit overrides the extension
+    tket_qir()
+        .arg("to-qir")
+        .arg(&circuit)
+        .args(["--emit", "ll", "-o"])
+        .arg(&bc)
+        .assert()
+        .success();
+    let ir = fs::read_to_string(&bc).expect("Reading failed.");
+    assert!(ir.contains("__quantum__qis__h__body"));
+}
+
+#[test]
+fn test_stdin() {
+    let json = fs::read(example("simple_H_pytket_circuit.json")).expect("Reading failed.");
+    let ir = stdout(tket_qir().arg("to-qir").write_stdin(json.clone()));
+    assert!(ir.contains("__quantum__qis__h__body"));
+    let ir = stdout(tket_qir().args(["to-qir", "-"]).write_stdin(json));
+    assert!(ir.contains("__quantum__qis__h__body"));
+}
+
+#[test]
+fn test_from_qir_stdin() {
+    // llvm-ir only parses files, so stdin goes through a temporary one
+    let ir = fs::read(example("SimpleGroverBaseProfile.ll")).expect("Reading failed.");
+    for args in [&["from-qir"][..], &["from-qir", "-"]] {
+        let json = stdout(tket_qir().args(args).write_stdin(ir.clone()));
+        let circ: serde_json::Value = serde_json::from_str(&json).expect("Invalid JSON.");
+        assert!(!circ["commands"]
+            .as_array()
+            .expect("No commands.")
+            .is_empty());
+    }
+
+    let err = stderr(
+        tket_qir()
+            .args(["from-qir", "-", "--entry-point", "missing"])
+            .write_stdin(ir),
+    );
+    assert!(err.contains("Function missing not found."));
+}
+
+#[test]
+fn test_bad_bindings() {
+    let circuit = example("simple_H_pytket_circuit.json");
+    let err = stderr(
+        tket_qir()
+            .arg("to-qir")
+            .arg(&circuit)
+            .args(["--bind", "theta"]),
+    );
+    assert!(err.contains("Expected NAME=VALUE, found theta"));
+    let err = stderr(
+        tket_qir()
+            .arg("to-qir")
+            .arg(&circuit)
+            .args(["--bind", "theta=half"]),
+    );
+    assert!(err.contains("Invalid value for theta: half"));
+}
+
+#[test]
+fn test_entry_point() {
+    let circuit = example("simple_H_pytket_circuit.json");
+    for profile in ["full", "base", "adaptive"] {
+        let ir = stdout(tket_qir().arg("to-qir").arg(&circuit).args([
+            "--profile",
+            profile,
+            "--entry-point",
+            "prepare",
+        ]));
+        assert!(ir.contains("define void @prepare()"), "{}", profile);
+    }
+}